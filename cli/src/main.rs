@@ -1,12 +1,42 @@
 extern crate soft_core;
 
 use soft_core::client::SoftClient;
-use std::io::{BufRead, stdin};
+use soft_core::server::auth::Permissions;
+use soft_core::tls;
+use std::env;
+use std::io::{BufRead, Read, Write, stdin, stdout};
 use std::net::TcpStream;
+use std::process;
+
+const USAGE: &str = "usage: soft [--host <host>] [--tls] [--ca <file>]";
 
 pub fn main() {
-    let stream = TcpStream::connect(("127.0.0.1", soft_core::DEFAULT_PORT)).unwrap();
-    let mut client = SoftClient::new(stream);
+    let mut host = "127.0.0.1".to_string();
+    let mut use_tls = false;
+    let mut ca = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--host" => host = args.next().unwrap_or_else(|| usage()),
+            "--tls" => use_tls = true,
+            "--ca" => {
+                ca = Some(args.next().unwrap_or_else(|| usage()));
+                use_tls = true;
+            }
+            _ => usage(),
+        }
+    }
+    let stream = TcpStream::connect((host.as_str(), soft_core::DEFAULT_PORT)).unwrap();
+    if use_tls {
+        let config = tls::client_config_from_file(ca).unwrap();
+        run(SoftClient::connect(tls::connect(&config, &host, stream).unwrap()).unwrap());
+    } else {
+        run(SoftClient::connect(stream).unwrap());
+    }
+}
+
+/// Run commands read from standard input
+fn run<S: Read + Write>(mut client: SoftClient<S>) {
     loop {
         let readed = readline();
         let splitted = readed.split_whitespace().map(|s| s.to_owned()).collect::<Vec<String>>();
@@ -18,9 +48,8 @@ pub fn main() {
             }
             "get" => {
                 if splitted.len() == 2 {
-                    let data = client.get(&splitted[1]).unwrap();
-                    let string = String::from_utf8(data).unwrap();
-                    println!("{}", string);
+                    client.get_to(&splitted[1], &mut stdout()).unwrap();
+                    println!();
                 }
             }
             "put" => {
@@ -67,6 +96,31 @@ pub fn main() {
                     client.rmdir(&splitted[1], true).unwrap();
                 }
             }
+            "useradd" => {
+                let permissions = match splitted.len() {
+                    3 => Permissions::all(),
+                    4 => Permissions::try_from(&splitted[3]).unwrap(),
+                    _ => continue,
+                };
+                client.add_user(&splitted[1], &splitted[2], permissions).unwrap();
+            }
+            "userdel" => {
+                if splitted.len() == 2 {
+                    client.remove_user(&splitted[1]).unwrap();
+                }
+            }
+            "passwd" => {
+                match splitted.len() {
+                    2 => client.passwd(&splitted[1]).unwrap(),
+                    3 => client.set_password(&splitted[1], &splitted[2]).unwrap(),
+                    _ => {}
+                }
+            }
+            "users" => {
+                for (user, permissions) in client.users().unwrap() {
+                    println!("- {} {}", user, permissions);
+                }
+            }
             "exit" => {
                 client.exit().unwrap();
                 break;
@@ -83,3 +137,9 @@ fn readline() -> String {
     lock.read_line(&mut buf).unwrap();
     buf
 }
+
+/// Print usage and exit
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(1)
+}
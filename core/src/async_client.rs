@@ -0,0 +1,248 @@
+//! Soft client for tokio streams
+//!
+//! Commands are run by a `SoftClient` on the blocking threads of tokio, so that they never
+//! block asynchronous tasks and speak exactly the protocol of the blocking client. Each
+//! running command, file transfers included, holds a thread of the blocking pool.
+use bridge::{Blocking, Bridge};
+use client::SoftClient;
+use error::*;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::runtime::Handle;
+use types::*;
+
+/// Soft client over an asynchronous stream
+///
+/// Must be created from a tokio runtime, which runs its commands.
+///
+/// ```no_run
+/// # extern crate soft_core;
+/// # extern crate tokio;
+/// use soft_core::async_client::AsyncSoftClient;
+/// use tokio::net::TcpStream;
+///
+/// let runtime = tokio::runtime::Runtime::new().unwrap();
+/// let _guard = runtime.enter();
+/// let stream = runtime.block_on(TcpStream::connect("127.0.0.1:9045")).unwrap();
+/// let mut client = runtime.block_on(AsyncSoftClient::connect(stream)).unwrap();
+/// runtime.block_on(client.login("user", "pass")).unwrap();
+/// ```
+pub struct AsyncSoftClient<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> {
+    client: Option<Arc<Mutex<SoftClient<Bridge<S>>>>>,
+    handle: Handle,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> AsyncSoftClient<S> {
+    /// Initialize a new client from stream
+    pub fn new(stream: S) -> AsyncSoftClient<S> {
+        let handle = Handle::current();
+        AsyncSoftClient::from_client(SoftClient::new(Bridge::new(stream, handle.clone())), handle)
+    }
+
+    /// Initialize a new client from stream and negotiate protocol with server
+    pub fn connect(stream: S) -> impl Future<Output = Result<AsyncSoftClient<S>>> {
+        let handle = Handle::current();
+        Blocking::spawn(&handle.clone(), move || {
+            let client = SoftClient::connect(Bridge::new(stream, handle.clone()))?;
+            Ok(AsyncSoftClient::from_client(client, handle))
+        })
+    }
+
+    /// Wrap blocking client
+    fn from_client(client: SoftClient<Bridge<S>>, handle: Handle) -> AsyncSoftClient<S> {
+        AsyncSoftClient {
+            client: Some(Arc::new(Mutex::new(client))),
+            handle,
+        }
+    }
+
+    /// Exchange protocol version and capabilities with soft server
+    pub fn hello(&mut self) -> impl Future<Output = Result<()>> {
+        self.call(|client| client.hello())
+    }
+
+    /// Send login information
+    pub fn login(&mut self, user: &str, pass: &str) -> impl Future<Output = Result<()>> {
+        let (user, pass) = (user.to_owned(), pass.to_owned());
+        self.call(move |client| client.login(&user, &pass))
+    }
+
+    /// Ask and get file from soft server
+    pub fn get(&mut self, path: &str) -> impl Future<Output = Result<Vec<u8>>> {
+        let path = path.to_owned();
+        self.call(move |client| client.get(&path))
+    }
+
+    /// Send a local file to soft server
+    pub fn put(&mut self,
+               local_path: &str,
+               remote_path: &str)
+               -> impl Future<Output = Result<()>> {
+        let (local_path, remote_path) = (local_path.to_owned(), remote_path.to_owned());
+        self.call(move |client| client.put(&local_path, &remote_path))
+    }
+
+    /// Send data to a file of soft server
+    pub fn put_data(&mut self,
+                    data: Vec<u8>,
+                    remote_path: &str)
+                    -> impl Future<Output = Result<()>> {
+        let remote_path = remote_path.to_owned();
+        self.call(move |client| client.put_from(&mut &data[..], data.len() as u64, &remote_path))
+    }
+
+    /// Ask size of a file
+    pub fn size(&mut self, path: &str) -> impl Future<Output = Result<u64>> {
+        let path = path.to_owned();
+        self.call(move |client| client.size(&path))
+    }
+
+    /// Ask metadata of a file, a directory or a symbolic link
+    pub fn stat(&mut self, path: &str) -> impl Future<Output = Result<Metadata>> {
+        let path = path.to_owned();
+        self.call(move |client| client.stat(&path))
+    }
+
+    /// Download the rest of a file partially present at local path
+    pub fn resume_get(&mut self,
+                      remote_path: &str,
+                      local_path: &str)
+                      -> impl Future<Output = Result<u64>> {
+        let (remote_path, local_path) = (remote_path.to_owned(), local_path.to_owned());
+        self.call(move |client| client.resume_get(&remote_path, &local_path))
+    }
+
+    /// Upload the rest of a local file partially present on server
+    pub fn resume_put(&mut self,
+                      local_path: &str,
+                      remote_path: &str)
+                      -> impl Future<Output = Result<u64>> {
+        let (local_path, remote_path) = (local_path.to_owned(), remote_path.to_owned());
+        self.call(move |client| client.resume_put(&local_path, &remote_path))
+    }
+
+    /// Ask and list file from soft server
+    pub fn list(&mut self, path: &str) -> impl Future<Output = Result<Vec<String>>> {
+        let path = path.to_owned();
+        self.call(move |client| client.list(&path))
+    }
+
+    /// Ask and list entries of a directory with their metadata from soft server
+    pub fn list_entries(&mut self, path: &str) -> impl Future<Output = Result<Vec<DirEntry>>> {
+        let path = path.to_owned();
+        self.call(move |client| client.list_entries(&path))
+    }
+
+    /// Ask and list file recursively from soft server
+    pub fn list_recursive(&mut self, path: &str) -> impl Future<Output = Result<Vec<String>>> {
+        let path = path.to_owned();
+        self.call(move |client| client.list_recursive(&path))
+    }
+
+    /// Get the current working directory
+    pub fn cwd(&mut self) -> impl Future<Output = Result<String>> {
+        self.call(|client| client.cwd())
+    }
+
+    /// Change directory
+    pub fn cd(&mut self, path: &str) -> impl Future<Output = Result<()>> {
+        let path = path.to_owned();
+        self.call(move |client| client.cd(&path))
+    }
+
+    /// Make directory
+    pub fn mkdir(&mut self, path: &str) -> impl Future<Output = Result<()>> {
+        let path = path.to_owned();
+        self.call(move |client| client.mkdir(&path))
+    }
+
+    /// Remove a file
+    pub fn rm(&mut self, path: &str) -> impl Future<Output = Result<()>> {
+        let path = path.to_owned();
+        self.call(move |client| client.rm(&path))
+    }
+
+    /// Remove a directory
+    pub fn rmdir(&mut self, path: &str, recursive: bool) -> impl Future<Output = Result<()>> {
+        let path = path.to_owned();
+        self.call(move |client| client.rmdir(&path, recursive))
+    }
+
+    /// Move or rename a file or a directory
+    pub fn rename(&mut self, src: &str, dst: &str) -> impl Future<Output = Result<()>> {
+        let (src, dst) = (src.to_owned(), dst.to_owned());
+        self.call(move |client| client.rename(&src, &dst))
+    }
+
+    /// Copy a file, or a directory if recursive is set
+    pub fn copy(&mut self,
+                src: &str,
+                dst: &str,
+                recursive: bool)
+                -> impl Future<Output = Result<()>> {
+        let (src, dst) = (src.to_owned(), dst.to_owned());
+        self.call(move |client| client.copy(&src, &dst, recursive))
+    }
+
+    /// Add a user to server, only allowed to admin accounts
+    pub fn add_user(&mut self,
+                    user: &str,
+                    pass: &str,
+                    permissions: Permissions)
+                    -> impl Future<Output = Result<()>> {
+        let (user, pass) = (user.to_owned(), pass.to_owned());
+        self.call(move |client| client.add_user(&user, &pass, permissions))
+    }
+
+    /// Remove a user from server, only allowed to admin accounts
+    pub fn remove_user(&mut self, user: &str) -> impl Future<Output = Result<()>> {
+        let user = user.to_owned();
+        self.call(move |client| client.remove_user(&user))
+    }
+
+    /// Change password of a user, only allowed to admin accounts for other users
+    pub fn set_password(&mut self, user: &str, pass: &str) -> impl Future<Output = Result<()>> {
+        let (user, pass) = (user.to_owned(), pass.to_owned());
+        self.call(move |client| client.set_password(&user, &pass))
+    }
+
+    /// Change password of logged in user
+    pub fn passwd(&mut self, pass: &str) -> impl Future<Output = Result<()>> {
+        let pass = pass.to_owned();
+        self.call(move |client| client.passwd(&pass))
+    }
+
+    /// List users of server with their permissions, only allowed to admin accounts
+    pub fn users(&mut self) -> impl Future<Output = Result<Vec<(String, Permissions)>>> {
+        self.call(|client| client.users())
+    }
+
+    /// Check presence of server
+    pub fn presence(&mut self) -> impl Future<Output = Result<()>> {
+        self.call(|client| client.presence())
+    }
+
+    /// Send to server an exit command
+    pub fn exit(&mut self) -> impl Future<Output = Result<()>> {
+        self.call(|client| client.exit())
+    }
+
+    /// Run a call of blocking client on the blocking pool
+    fn call<T, F>(&mut self, call: F) -> Blocking<T>
+        where T: Send + 'static,
+              F: FnOnce(&mut SoftClient<Bridge<S>>) -> Result<T> + Send + 'static
+    {
+        let client = self.client.clone().unwrap();
+        Blocking::spawn(&self.handle, move || call(&mut client.lock().unwrap()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> Drop for AsyncSoftClient<S> {
+    /// Exit on the blocking pool, since blocking client exits when dropped
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            self.handle.spawn_blocking(move || drop(client));
+        }
+    }
+}
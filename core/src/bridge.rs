@@ -0,0 +1,126 @@
+//! Blocking access to asynchronous streams
+//!
+//! Lets the blocking client and server code run over tokio streams, from threads of the
+//! blocking pool of tokio. Every blocking read or write waits on the runtime, so the thread
+//! stays busy as long as the peer is slow.
+use common::{BUFFER_SIZE, Encoding, FRAME_HEADER_SIZE, MAX_FRAME_PAYLOAD};
+use std::cmp;
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+
+/// Asynchronous stream usable as a blocking one outside of asynchronous tasks
+pub struct Bridge<S> {
+    stream: S,
+    /// Data read in advance, from pos
+    buf: Vec<u8>,
+    pos: usize,
+    handle: Handle,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Bridge<S> {
+    /// Wrap stream, running its operations on runtime of handle
+    pub fn new(stream: S, handle: Handle) -> Bridge<S> {
+        Bridge {
+            stream,
+            buf: Vec::new(),
+            pos: 0,
+            handle,
+        }
+    }
+
+    /// Read until a whole command in encoding is buffered, returning false at end of stream
+    ///
+    /// Reading the command then never blocks.
+    pub fn poll_command(&mut self, cx: &mut Context, encoding: Encoding) -> Poll<io::Result<bool>> {
+        loop {
+            if has_command(&self.buf[self.pos..], encoding) {
+                return Poll::Ready(Ok(true));
+            }
+            if self.buf.len() - self.pos > FRAME_HEADER_SIZE + MAX_FRAME_PAYLOAD {
+                return Poll::Ready(Err(io::Error::new(io::ErrorKind::InvalidData,
+                                                      "command is too long")));
+            }
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+            let mut chunk = [0; BUFFER_SIZE];
+            let mut chunk = ReadBuf::new(&mut chunk);
+            match Pin::new(&mut self.stream).poll_read(cx, &mut chunk) {
+                Poll::Ready(Ok(())) if chunk.filled().is_empty() => return Poll::Ready(Ok(false)),
+                Poll::Ready(Ok(())) => self.buf.extend_from_slice(chunk.filled()),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Read for Bridge<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos < self.buf.len() {
+            let len = cmp::min(buf.len(), self.buf.len() - self.pos);
+            buf[..len].copy_from_slice(&self.buf[self.pos..self.pos + len]);
+            self.pos += len;
+            return Ok(len);
+        }
+        self.handle.block_on(self.stream.read(buf))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Write for Bridge<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.handle.block_on(self.stream.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.handle.block_on(self.stream.flush())
+    }
+}
+
+/// Check if data starts with a whole command
+///
+/// A frame announcing a payload too large is whole, so that reading it fails.
+fn has_command(data: &[u8], encoding: Encoding) -> bool {
+    match encoding {
+        Encoding::Text => data.contains(&b'\n'),
+        Encoding::Frame => {
+            if data.len() < FRAME_HEADER_SIZE {
+                return false;
+            }
+            let mut len = [0; 4];
+            len.copy_from_slice(&data[5..9]);
+            let len = u32::from_be_bytes(len) as usize;
+            len > MAX_FRAME_PAYLOAD || data.len() >= FRAME_HEADER_SIZE + len
+        }
+    }
+}
+
+/// Result of a blocking call run by the blocking pool
+///
+/// A panic of the call is returned as an I/O error.
+pub struct Blocking<T>(JoinHandle<::error::Result<T>>);
+
+impl<T: Send + 'static> Blocking<T> {
+    /// Run call on the blocking pool of runtime
+    pub fn spawn<F>(handle: &Handle, call: F) -> Blocking<T>
+        where F: FnOnce() -> ::error::Result<T> + Send + 'static
+    {
+        Blocking(handle.spawn_blocking(call))
+    }
+}
+
+impl<T> Future for Blocking<T> {
+    type Output = ::error::Result<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<::error::Result<T>> {
+        match Pin::new(&mut self.0).poll(cx) {
+            Poll::Ready(Ok(result)) => Poll::Ready(result),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(io::Error::other(e).into())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
@@ -1,6 +1,11 @@
 //! Soft client to talk with soft server
+use {MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use common::{Encoding, Transport};
 use error::*;
-use std::io::{Read, Write};
+use scram;
+use std::cmp;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
 use types::*;
 
 /// Soft client
@@ -10,40 +15,196 @@ use types::*;
 /// use std::net::TcpStream;
 ///
 /// let stream = TcpStream::connect("127.0.0.1:9045").unwrap();
-/// let mut client = SoftClient::new(stream);
+/// let mut client = SoftClient::connect(stream).unwrap();
 /// ```
 pub struct SoftClient<S: Read + Write> {
-    stream: S,
+    transport: Transport<S>,
     exited: bool,
+    version: Option<u32>,
+    capabilities: Vec<Capability>,
 }
 
 impl<S: Read + Write> SoftClient<S> {
     /// Initialize a new client from stream
     pub fn new(stream: S) -> SoftClient<S> {
         SoftClient {
-            stream: stream,
+            transport: Transport::new(stream),
             exited: false,
+            version: None,
+            capabilities: Vec::new(),
         }
     }
 
+    /// Initialize a new client from stream and negotiate protocol with server
+    pub fn connect(stream: S) -> Result<SoftClient<S>> {
+        let mut client = SoftClient::new(stream);
+        client.hello()?;
+        Ok(client)
+    }
+
+    /// Exchange protocol version and capabilities with soft server
+    pub fn hello(&mut self) -> Result<()> {
+        self.hello_with(Capability::supported())
+    }
+
+    /// Exchange protocol version with soft server, offering only some capabilities
+    pub fn hello_with(&mut self, capabilities: Vec<Capability>) -> Result<()> {
+        self.write_command(Command::Hello(PROTOCOL_VERSION, capabilities.clone()))?;
+        let status = self.read_status()?;
+        let line = self.read_line()?;
+        let (version, server_capabilities) = match Command::try_from(&line)? {
+            Command::Hello(v, c) => (v, c),
+            _ => bail!(ErrorKind::InvalidCommand(line)),
+        };
+        if status.is_negative() || version < MIN_PROTOCOL_VERSION {
+            bail!(ErrorKind::UnsupportedVersion(PROTOCOL_VERSION, version));
+        }
+        self.version = Some(cmp::min(version, PROTOCOL_VERSION));
+        self.capabilities = Capability::negotiate(&capabilities, &server_capabilities);
+        if self.has_capability(&Capability::Frame) {
+            self.transport.set_encoding(Encoding::Frame);
+        }
+        let checksum = self.has_capability(&Capability::Checksum);
+        self.transport.set_checksum(checksum);
+        Ok(())
+    }
+
+    /// Get negotiated protocol version, if handshake has been done
+    pub fn version(&self) -> Option<u32> {
+        self.version
+    }
+
+    /// Get capabilities supported by both client and server
+    pub fn capabilities(&self) -> &[Capability] {
+        &self.capabilities
+    }
+
+    /// Check if a capability has been negotiated with server
+    pub fn has_capability(&self, capability: &Capability) -> bool {
+        self.capabilities.contains(capability)
+    }
+
     /// Login to soft server
+    ///
+    /// If server supports it, login uses a challenge-response so that password is never
+    /// sent, and server proves it knows the password too.
     pub fn login(&mut self, user: &str, pass: &str) -> Result<()> {
+        if self.has_capability(&Capability::Scram) && user != "anonymous" {
+            return self.scram_login(user, pass);
+        }
         self.write_command(Command::Login(user.into(), pass.into()))?;
         self.check_status()
     }
 
+    /// Login with a challenge-response
+    fn scram_login(&mut self, user: &str, pass: &str) -> Result<()> {
+        let client_nonce = scram::nonce()?;
+        self.write_command(Command::Auth(user.into(), client_nonce.clone()))?;
+        self.check_status()?;
+        let line = self.read_line()?;
+        let args = split_args(&line)?;
+        let salt = args.get(1).and_then(|s| ::common::from_hex(s));
+        let iterations = args.get(2).and_then(|i| i.parse::<u32>().ok());
+        let (salt, iterations) = match (salt, iterations) {
+            (Some(salt), Some(iterations))
+                if args.len() == 3 && args[0].starts_with(&client_nonce) && iterations > 0 => {
+                (salt, iterations)
+            }
+            _ => bail!(ErrorKind::InvalidResponse(line)),
+        };
+        let salted_password = scram::salted_password(pass, &salt, iterations);
+        let client_key = scram::client_key(&salted_password);
+        let stored_key = scram::stored_key(&client_key);
+        let auth_message = scram::auth_message(user, &client_nonce, &args[0], &args[1], iterations);
+        let proof = scram::client_proof(&client_key, &stored_key, &auth_message);
+        self.write_command(Command::Proof(::common::to_hex(&proof)))?;
+        self.check_status()?;
+        let line = self.read_line()?;
+        let server_key = scram::server_key(&salted_password);
+        let signature = scram::server_signature(&server_key, &auth_message);
+        match ::common::from_hex(line.trim()) {
+            Some(ref s) if ::common::constant_time_eq(s, &signature) => Ok(()),
+            _ => bail!(ErrorKind::InvalidResponse(line)),
+        }
+    }
+
     /// Ask and get file from soft server
     pub fn get(&mut self, path: &str) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.get_to(path, &mut data)?;
+        Ok(data)
+    }
+
+    /// Ask file from soft server and write it to writer, returning its size
+    pub fn get_to<W: Write>(&mut self, path: &str, writer: &mut W) -> Result<u64> {
         self.write_command(Command::Get(path.into()))?;
         self.check_status()?;
-        self.recv_file()
+        ::common::recv_file_to(&mut self.transport, writer)
     }
 
     /// Ask and put file to soft server
     pub fn put(&mut self, local_path: &str, remote_path: &str) -> Result<()> {
+        let mut file = File::open(local_path)?;
+        let len = file.metadata()?.len();
+        self.put_from(&mut file, len, remote_path)
+    }
+
+    /// Ask and put len bytes read from reader to soft server
+    pub fn put_from<R: Read>(&mut self, reader: &mut R, len: u64, remote_path: &str) -> Result<()> {
         self.write_command(Command::Put(remote_path.into()))?;
         self.check_status()?;
-        self.send_file(local_path)
+        self.send_data(reader, len)
+    }
+
+    /// Get size of a file on soft server
+    pub fn size(&mut self, path: &str) -> Result<u64> {
+        self.write_command(Command::Size(path.into()))?;
+        self.check_status()?;
+        self.transport.read_size()
+    }
+
+    /// Get metadata of a file on soft server
+    pub fn stat(&mut self, path: &str) -> Result<Metadata> {
+        self.write_command(Command::Stat(path.into()))?;
+        self.check_status()?;
+        let line = self.read_line()?;
+        Metadata::try_from(line)
+    }
+
+    /// Continue downloading a file into local path, returning the number of bytes received
+    ///
+    /// Download starts at the current size of the local file.
+    pub fn resume_get(&mut self, remote_path: &str, local_path: &str) -> Result<u64> {
+        let mut file = OpenOptions::new().append(true).create(true).open(local_path)?;
+        let offset = file.metadata()?.len();
+        self.write_command(Command::GetAt(remote_path.into(), offset))?;
+        self.check_status()?;
+        ::common::recv_file_to(&mut self.transport, &mut file)
+    }
+
+    /// Continue uploading a local file, returning the number of bytes sent
+    ///
+    /// Upload starts after the data received by server before an interrupted upload, or
+    /// at the beginning if there is none.
+    pub fn resume_put(&mut self, local_path: &str, remote_path: &str) -> Result<u64> {
+        let offset = self.resume_offset(remote_path)?;
+        let mut file = File::open(local_path)?;
+        let len = file.metadata()?.len();
+        if offset > len {
+            bail!(ErrorKind::InvalidOffset);
+        }
+        file.seek(SeekFrom::Start(offset))?;
+        self.write_command(Command::PutAt(remote_path.into(), offset))?;
+        self.check_status()?;
+        self.send_data(&mut file, len - offset)?;
+        Ok(len - offset)
+    }
+
+    /// Get the number of bytes server kept from an interrupted upload to a file
+    pub fn resume_offset(&mut self, remote_path: &str) -> Result<u64> {
+        self.write_command(Command::Resume(remote_path.into()))?;
+        self.check_status()?;
+        self.transport.read_size()
     }
 
     /// Ask and list file from soft server
@@ -53,6 +214,14 @@ impl<S: Read + Write> SoftClient<S> {
         self.recv_list_file()
     }
 
+    /// Ask and list entries of a directory with their metadata from soft server
+    pub fn list_entries(&mut self, path: &str) -> Result<Vec<DirEntry>> {
+        self.write_command(Command::ListLong(path.into()))?;
+        self.check_status()?;
+        let lines = self.recv_list_file()?;
+        lines.iter().map(DirEntry::try_from).collect()
+    }
+
     /// Ask and list file recursively from soft server
     pub fn list_recursive(&mut self, path: &str) -> Result<Vec<String>> {
         let mut list = self.list(path)?;
@@ -72,7 +241,12 @@ impl<S: Read + Write> SoftClient<S> {
     pub fn cwd(&mut self) -> Result<String> {
         self.write_command(Command::Cwd)?;
         self.check_status()?;
-        self.read_line()
+        let line = self.read_line()?;
+        let mut args = split_args(&line)?;
+        if args.len() != 1 {
+            bail!(ErrorKind::InvalidResponse(line));
+        }
+        Ok(args.remove(0))
     }
 
     /// Change directory
@@ -99,6 +273,62 @@ impl<S: Read + Write> SoftClient<S> {
         self.check_status()
     }
 
+    /// Move or rename a file or a directory
+    pub fn rename(&mut self, src: &str, dst: &str) -> Result<()> {
+        self.write_command(Command::Mv(src.into(), dst.into()))?;
+        self.check_status()
+    }
+
+    /// Copy a file, or a directory if recursive is set
+    pub fn copy(&mut self, src: &str, dst: &str, recursive: bool) -> Result<()> {
+        self.write_command(Command::Cp(src.into(), dst.into(), recursive))?;
+        self.check_status()
+    }
+
+    /// Add a user to server, only allowed to admin accounts
+    pub fn add_user(&mut self, user: &str, pass: &str, permissions: Permissions) -> Result<()> {
+        self.write_command(Command::UserAdd(user.into(), pass.into(), Some(permissions)))?;
+        self.check_user_status(user)
+    }
+
+    /// Remove a user from server, only allowed to admin accounts
+    pub fn remove_user(&mut self, user: &str) -> Result<()> {
+        self.write_command(Command::UserDel(user.into()))?;
+        self.check_user_status(user)
+    }
+
+    /// Change password of a user, only allowed to admin accounts for other users
+    pub fn set_password(&mut self, user: &str, pass: &str) -> Result<()> {
+        self.write_command(Command::Passwd(Some(user.into()), pass.into()))?;
+        self.check_user_status(user)
+    }
+
+    /// Change password of logged in user
+    pub fn passwd(&mut self, pass: &str) -> Result<()> {
+        self.write_command(Command::Passwd(None, pass.into()))?;
+        self.check_status()
+    }
+
+    /// List users of server with their permissions, only allowed to admin accounts
+    pub fn users(&mut self) -> Result<Vec<(String, Permissions)>> {
+        self.write_command(Command::Users)?;
+        self.check_status()?;
+        let lines = self.recv_list_file()?;
+        let mut users = Vec::with_capacity(lines.len());
+        for line in lines {
+            let mut args = split_args(&line)?;
+            if args.len() != 2 {
+                bail!(ErrorKind::InvalidResponse(line));
+            }
+            let permissions = match Permissions::try_from(&args[1]) {
+                Ok(permissions) => permissions,
+                Err(_) => bail!(ErrorKind::InvalidResponse(line)),
+            };
+            users.push((args.remove(0), permissions));
+        }
+        Ok(users)
+    }
+
     /// Check presence of server
     pub fn presence(&mut self) -> Result<()> {
         self.write_command(Command::Presence)?;
@@ -115,61 +345,100 @@ impl<S: Read + Write> SoftClient<S> {
     /// Check sended status
     fn check_status(&mut self) -> Result<()> {
         let status = self.read_status()?;
+        if status == Status::Disconnected && !self.exited {
+            // Server is shutting down, there is nobody left to say goodbye to
+            self.exited = true;
+            bail!(ErrorKind::Disconnected);
+        }
         if status.is_negative() {
             match status {
                 Status::WrongLogin => bail!(ErrorKind::InvalidLogin),
                 Status::NotConnected => bail!(ErrorKind::NotConnected),
-                _ => {}
+                Status::InvalidOffset => bail!(ErrorKind::InvalidOffset),
+                Status::ChecksumMismatch => bail!(ErrorKind::ChecksumMismatch),
+                Status::PermissionDenied => bail!(ErrorKind::PermissionDenied),
+                Status::Unsupported => bail!(ErrorKind::Unsupported),
+                s => bail!(ErrorKind::UnexpectedStatus(s)),
             }
         }
         Ok(())
     }
 
+    /// Check sended status of a command about a user
+    fn check_user_status(&mut self, user: &str) -> Result<()> {
+        match self.check_status() {
+            Err(Error(ErrorKind::UnexpectedStatus(Status::UserExists), _)) => {
+                bail!(ErrorKind::UserExists(user.to_owned()))
+            }
+            Err(Error(ErrorKind::UnexpectedStatus(Status::UserUnknown), _)) => {
+                bail!(ErrorKind::UserUnknown(user.to_owned()))
+            }
+            Err(Error(ErrorKind::UnexpectedStatus(Status::InvalidUserName), _)) => {
+                bail!(ErrorKind::InvalidUserName(user.to_owned()))
+            }
+            r => r,
+        }
+    }
+
+    /// Send upload data and wait for server to verify it
+    fn send_data<R: Read>(&mut self, reader: &mut R, len: u64) -> Result<()> {
+        ::common::send_file_from(&mut self.transport, reader, len)?;
+        if self.transport.checksum() {
+            self.check_status()?;
+        }
+        Ok(())
+    }
+
     // Low level functions
 
     /// Send a command to server
     ///
     /// Warning: this is a low level function
     pub fn write_command(&mut self, command: Command) -> Result<()> {
-        self.stream.write(format!("{}\n", command).as_bytes())?;
-        Ok(())
+        match self.transport.write_command(&command) {
+            Err(e) => {
+                // Server may have left, telling why before closing
+                if !self.exited && self.transport.read_status().ok() == Some(Status::Disconnected) {
+                    self.exited = true;
+                    bail!(ErrorKind::Disconnected);
+                }
+                Err(e)
+            }
+            r => r,
+        }
     }
 
     /// Receive status from server
     ///
     /// Warning: this is a low level function
     pub fn read_status(&mut self) -> Result<Status> {
-        let mut buf = [0];
-        self.stream.read(&mut buf)?;
-        Ok(Status::from(buf[0]))
+        self.transport.read_status()
     }
 
     /// Receive file from soft server
     ///
     /// Warning: this is a low level function
     pub fn recv_file(&mut self) -> Result<Vec<u8>> {
-        ::common::recv_file(&mut self.stream)
+        ::common::recv_file(&mut self.transport)
     }
 
     /// Receive list of file from soft server
     ///
     /// Warning: this is a low level function
     pub fn recv_list_file(&mut self) -> Result<Vec<String>> {
-        ::common::recv_list_file(&mut self.stream)
+        ::common::recv_list_file(&mut self.transport)
     }
 
     /// Send file to soft server
     ///
     /// Warning: this is a low level function
     pub fn send_file(&mut self, path: &str) -> Result<()> {
-        ::common::send_file(&mut self.stream, path)
+        ::common::send_file(&mut self.transport, path)
     }
 
     /// Read a single line
     pub fn read_line(&mut self) -> Result<String> {
-        let mut buf = String::new();
-        ::common::read_line(&mut self.stream, &mut buf)?;
-        Ok(buf)
+        self.transport.read_line()
     }
 }
 
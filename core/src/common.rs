@@ -1,62 +1,477 @@
 //! Common module contains all function needed by server and client
 use error::*;
+use sha2::{Digest, Sha256};
+use std::cmp;
 use std::fs;
-use std::io::{Read, Write};
-use std::mem;
+use std::io::{self, Read, Write};
 use std::path::Path;
+use types::{Command, Status};
+
+/// Size of a frame header: kind (1 byte), request id (4 bytes), payload length (4 bytes)
+pub const FRAME_HEADER_SIZE: usize = 9;
+
+/// Request id of frames sent by server outside of any request
+pub const NOTICE_ID: u32 = 0;
+
+/// Size of the buffer used to transfer files
+pub const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Size of the digest appended to transfers
+pub const DIGEST_SIZE: usize = 32;
+
+/// Maximum size of a frame payload
+pub const MAX_FRAME_PAYLOAD: usize = 16 * 1024 * 1024;
+
+/// Encoding used on the wire
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    /// Newline terminated commands and raw status bytes
+    Text,
+    /// Length prefixed binary frames
+    Frame,
+}
+
+/// Kind of a binary frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameKind {
+    /// Command sent by client, payload is the textual command
+    Command = 1,
+    /// Status of a command, payload is a single byte
+    Status = 2,
+    /// Single line of text
+    Line = 3,
+    /// Size of following data, payload is a big-endian u64
+    Size = 4,
+    /// Chunk of data
+    Data = 5,
+    /// Digest of transferred data
+    Digest = 6,
+}
+
+impl FrameKind {
+    /// Convert a byte to a frame kind
+    pub fn from_u8(from: u8) -> Option<FrameKind> {
+        match from {
+            1 => Some(FrameKind::Command),
+            2 => Some(FrameKind::Status),
+            3 => Some(FrameKind::Line),
+            4 => Some(FrameKind::Size),
+            5 => Some(FrameKind::Data),
+            6 => Some(FrameKind::Digest),
+            _ => None,
+        }
+    }
+}
+
+/// Binary frame, all integers are big-endian
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    /// Kind of frame
+    pub kind: FrameKind,
+    /// Id of the request this frame belongs to
+    pub id: u32,
+    /// Frame content
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Create a new frame
+    pub fn new(kind: FrameKind, id: u32, payload: Vec<u8>) -> Frame {
+        Frame {
+            kind,
+            id,
+            payload,
+        }
+    }
+
+    /// Encode frame header
+    pub fn header(&self) -> [u8; FRAME_HEADER_SIZE] {
+        let mut header = [0; FRAME_HEADER_SIZE];
+        header[0] = self.kind as u8;
+        header[1..5].copy_from_slice(&self.id.to_be_bytes());
+        header[5..9].copy_from_slice(&(self.payload.len() as u32).to_be_bytes());
+        header
+    }
+
+    /// Decode frame header, returning kind, request id and payload length
+    pub fn parse_header(header: &[u8; FRAME_HEADER_SIZE]) -> Result<(FrameKind, u32, usize)> {
+        let kind = match FrameKind::from_u8(header[0]) {
+            Some(k) => k,
+            None => bail!(ErrorKind::InvalidFrame(format!("unknown frame kind {}", header[0]))),
+        };
+        let mut id = [0; 4];
+        id.copy_from_slice(&header[1..5]);
+        let mut len = [0; 4];
+        len.copy_from_slice(&header[5..9]);
+        let len = u32::from_be_bytes(len) as usize;
+        if len > MAX_FRAME_PAYLOAD {
+            bail!(ErrorKind::InvalidFrame(format!("payload of {} bytes is too large", len)));
+        }
+        Ok((kind, u32::from_be_bytes(id), len))
+    }
+
+    /// Write frame to stream
+    pub fn write_to<W: Write>(&self, stream: &mut W) -> Result<()> {
+        stream.write_all(&self.header())?;
+        stream.write_all(&self.payload)?;
+        Ok(())
+    }
+
+    /// Read frame from stream
+    pub fn read_from<R: Read>(stream: &mut R) -> Result<Frame> {
+        let mut header = [0; FRAME_HEADER_SIZE];
+        stream.read_exact(&mut header)?;
+        let (kind, id, len) = Frame::parse_header(&header)?;
+        let mut payload = vec![0; len];
+        stream.read_exact(&mut payload)?;
+        Ok(Frame::new(kind, id, payload))
+    }
+}
+
+/// Stream wrapper speaking the negotiated encoding
+pub struct Transport<S: Read + Write> {
+    stream: S,
+    encoding: Encoding,
+    checksum: bool,
+    id: u32,
+    pending: Vec<u8>,
+    pending_pos: usize,
+}
+
+impl<S: Read + Write> Transport<S> {
+    /// Wrap a stream, starting with text encoding
+    pub fn new(stream: S) -> Transport<S> {
+        Transport {
+            stream,
+            encoding: Encoding::Text,
+            checksum: false,
+            id: 0,
+            pending: Vec::new(),
+            pending_pos: 0,
+        }
+    }
+
+    /// Get underlying stream
+    #[cfg(feature = "async")]
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// Get current encoding
+    #[cfg(feature = "async")]
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Switch to another encoding
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
+
+    /// Check if transfers carry a digest
+    pub fn checksum(&self) -> bool {
+        self.checksum
+    }
+
+    /// Enable or disable transfer digests
+    pub fn set_checksum(&mut self, checksum: bool) {
+        self.checksum = checksum;
+    }
+
+    /// Send a command, starting a new request
+    pub fn write_command(&mut self, command: &Command) -> Result<()> {
+        self.id = self.id.wrapping_add(1);
+        if self.id == NOTICE_ID {
+            self.id += 1;
+        }
+        match self.encoding {
+            Encoding::Text => {
+                self.stream.write_all(format!("{}\n", command).as_bytes())?;
+                Ok(())
+            }
+            Encoding::Frame => self.write_frame(FrameKind::Command, command.to_string().into_bytes()),
+        }
+    }
+
+    /// Receive a command, replies will carry its request id
+    pub fn read_command(&mut self) -> Result<Command> {
+        match self.encoding {
+            Encoding::Text => {
+                let mut buf = String::new();
+                read_line(&mut self.stream, &mut buf)?;
+                Command::try_from(buf)
+            }
+            Encoding::Frame => {
+                let frame = Frame::read_from(&mut self.stream)?;
+                if frame.kind != FrameKind::Command {
+                    bail!(ErrorKind::InvalidFrame(format!("expected command, got {:?}", frame.kind)));
+                }
+                self.id = frame.id;
+                Command::try_from(frame_to_string(frame.payload)?)
+            }
+        }
+    }
+
+    /// Send status of current request
+    pub fn write_status(&mut self, status: Status) -> Result<()> {
+        match self.encoding {
+            Encoding::Text => {
+                self.stream.write_all(&[status as u8])?;
+                Ok(())
+            }
+            Encoding::Frame => self.write_frame(FrameKind::Status, vec![status as u8]),
+        }
+    }
+
+    /// Send status outside of any request, like the shutdown of server
+    pub fn write_notice(&mut self, status: Status) -> Result<()> {
+        match self.encoding {
+            Encoding::Text => self.write_status(status),
+            Encoding::Frame => {
+                Frame::new(FrameKind::Status, NOTICE_ID, vec![status as u8]).write_to(&mut self.stream)
+            }
+        }
+    }
+
+    /// Receive status of current request, or a notice of server
+    pub fn read_status(&mut self) -> Result<Status> {
+        match self.encoding {
+            Encoding::Text => {
+                let mut buf = [0];
+                self.stream.read_exact(&mut buf)?;
+                Ok(Status::from(buf[0]))
+            }
+            Encoding::Frame => {
+                let payload = self.read_frame(FrameKind::Status)?;
+                if payload.len() != 1 {
+                    bail!(ErrorKind::InvalidFrame("status must be a single byte".into()));
+                }
+                Ok(Status::from(payload[0]))
+            }
+        }
+    }
+
+    /// Send a single line
+    pub fn write_line(&mut self, line: &str) -> Result<()> {
+        match self.encoding {
+            Encoding::Text => {
+                self.stream.write_all(format!("{}\n", line).as_bytes())?;
+                Ok(())
+            }
+            Encoding::Frame => self.write_frame(FrameKind::Line, line.as_bytes().to_vec()),
+        }
+    }
+
+    /// Receive a single line
+    pub fn read_line(&mut self) -> Result<String> {
+        match self.encoding {
+            Encoding::Text => {
+                let mut buf = String::new();
+                read_line(&mut self.stream, &mut buf)?;
+                Ok(buf)
+            }
+            Encoding::Frame => {
+                let payload = self.read_frame(FrameKind::Line)?;
+                frame_to_string(payload)
+            }
+        }
+    }
+
+    /// Send size of following data
+    pub fn write_size(&mut self, size: u64) -> Result<()> {
+        match self.encoding {
+            Encoding::Text => {
+                self.stream.write_all(&u64_as_bytes(size))?;
+                Ok(())
+            }
+            Encoding::Frame => self.write_frame(FrameKind::Size, size.to_be_bytes().to_vec()),
+        }
+    }
+
+    /// Receive size of following data
+    pub fn read_size(&mut self) -> Result<u64> {
+        let mut buf = [0; 8];
+        match self.encoding {
+            Encoding::Text => {
+                self.stream.read_exact(&mut buf)?;
+                Ok(bytes_to_u64(buf))
+            }
+            Encoding::Frame => {
+                let payload = self.read_frame(FrameKind::Size)?;
+                if payload.len() != 8 {
+                    bail!(ErrorKind::InvalidFrame("size must be 8 bytes".into()));
+                }
+                buf.copy_from_slice(&payload);
+                Ok(u64::from_be_bytes(buf))
+            }
+        }
+    }
+
+    /// Send a chunk of data
+    pub fn write_data(&mut self, data: &[u8]) -> Result<()> {
+        match self.encoding {
+            Encoding::Text => {
+                self.stream.write_all(data)?;
+                Ok(())
+            }
+            Encoding::Frame => self.write_frame(FrameKind::Data, data.to_vec()),
+        }
+    }
+
+    /// Receive data into buffer, returning the number of bytes read
+    pub fn read_data(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self.encoding {
+            Encoding::Text => Ok(self.stream.read(buf)?),
+            Encoding::Frame => {
+                while self.pending_pos >= self.pending.len() {
+                    self.pending = self.read_frame(FrameKind::Data)?;
+                    self.pending_pos = 0;
+                }
+                let available = &self.pending[self.pending_pos..];
+                let len = cmp::min(available.len(), buf.len());
+                buf[..len].copy_from_slice(&available[..len]);
+                self.pending_pos += len;
+                Ok(len)
+            }
+        }
+    }
+
+    /// Send digest of transferred data
+    pub fn write_digest(&mut self, digest: &[u8; DIGEST_SIZE]) -> Result<()> {
+        match self.encoding {
+            Encoding::Text => {
+                self.stream.write_all(digest)?;
+                Ok(())
+            }
+            Encoding::Frame => self.write_frame(FrameKind::Digest, digest.to_vec()),
+        }
+    }
+
+    /// Receive digest of transferred data
+    pub fn read_digest(&mut self) -> Result<[u8; DIGEST_SIZE]> {
+        let mut digest = [0; DIGEST_SIZE];
+        match self.encoding {
+            Encoding::Text => self.stream.read_exact(&mut digest)?,
+            Encoding::Frame => {
+                let payload = self.read_frame(FrameKind::Digest)?;
+                if payload.len() != DIGEST_SIZE {
+                    bail!(ErrorKind::InvalidFrame(format!("digest must be {} bytes", DIGEST_SIZE)));
+                }
+                digest.copy_from_slice(&payload);
+            }
+        }
+        Ok(digest)
+    }
+
+    /// Write a frame for current request
+    fn write_frame(&mut self, kind: FrameKind, payload: Vec<u8>) -> Result<()> {
+        Frame::new(kind, self.id, payload).write_to(&mut self.stream)
+    }
+
+    /// Read a frame of current request and return its payload
+    fn read_frame(&mut self, kind: FrameKind) -> Result<Vec<u8>> {
+        let frame = Frame::read_from(&mut self.stream)?;
+        if frame.kind != kind {
+            bail!(ErrorKind::InvalidFrame(format!("expected {:?}, got {:?}", kind, frame.kind)));
+        }
+        if frame.id != self.id && !(kind == FrameKind::Status && frame.id == NOTICE_ID) {
+            bail!(ErrorKind::InvalidFrame(format!("expected request {}, got {}", self.id, frame.id)));
+        }
+        Ok(frame.payload)
+    }
+}
+
+/// Convert a frame payload to string
+fn frame_to_string(payload: Vec<u8>) -> Result<String> {
+    match String::from_utf8(payload) {
+        Ok(s) => Ok(s),
+        Err(_) => bail!(ErrorKind::InvalidFrame("payload is not valid utf-8".into())),
+    }
+}
 
 /// Receive file from stream
-pub fn recv_file<R: Read>(stream: &mut R) -> Result<Vec<u8>> {
-    let size = read_size(stream)? as usize;
-    let mut data = Vec::with_capacity(size);
-    let mut buf = [0; 100];
+pub fn recv_file<S: Read + Write>(transport: &mut Transport<S>) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    recv_file_to(transport, &mut data)?;
+    Ok(data)
+}
+
+/// Receive file from stream and write it to writer, returning the received size
+pub fn recv_file_to<S: Read + Write, W: Write>(transport: &mut Transport<S>,
+                                               writer: &mut W)
+                                               -> Result<u64> {
+    let size = transport.read_size()?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; BUFFER_SIZE];
     let mut read_size = 0;
     while read_size < size {
-        let to_read = size - read_size;
-        let readed = if to_read < 100 {
-            stream.read(&mut buf[0..to_read])?
-        } else {
-            stream.read(&mut buf)?
-        };
-        read_size += readed;
-        data.extend_from_slice(&buf[0..readed]);
+        let to_read = cmp::min(size - read_size, BUFFER_SIZE as u64) as usize;
+        let readed = transport.read_data(&mut buf[0..to_read])?;
+        if readed == 0 {
+            bail!(io::Error::new(io::ErrorKind::UnexpectedEof, "transfer ended early"));
+        }
+        writer.write_all(&buf[0..readed])?;
+        hasher.update(&buf[0..readed]);
+        read_size += readed as u64;
     }
-    Ok(data)
+    if transport.checksum() {
+        let digest = transport.read_digest()?;
+        if hasher.finalize().as_slice() != digest {
+            bail!(ErrorKind::ChecksumMismatch);
+        }
+    }
+    Ok(size)
 }
 
 /// Receive list of files from stream
-pub fn recv_list_file<R: Read>(stream: &mut R) -> Result<Vec<String>> {
-    let size = read_size(stream)?;
+pub fn recv_list_file<S: Read + Write>(transport: &mut Transport<S>) -> Result<Vec<String>> {
+    let size = transport.read_size()?;
     let mut list = Vec::new();
     for _ in 0..size {
-        let mut buf = String::new();
-        read_line(stream, &mut buf)?;
-        list.push(buf);
+        list.push(transport.read_line()?);
     }
     Ok(list)
 }
 
 /// Send file to stream
-pub fn send_file<W: Write>(stream: &mut W, path: &str) -> Result<()> {
+pub fn send_file<S: Read + Write>(transport: &mut Transport<S>, path: &str) -> Result<()> {
     let mut file = fs::File::open(path)?;
     let size = file.metadata()?.len();
-    stream.write(&u64_as_bytes(size))?;
+    send_file_from(transport, &mut file, size)
+}
+
+/// Send size bytes read from reader to stream
+pub fn send_file_from<S: Read + Write, R: Read>(transport: &mut Transport<S>,
+                                                reader: &mut R,
+                                                size: u64)
+                                                -> Result<()> {
+    transport.write_size(size)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; BUFFER_SIZE];
     let mut write_size = 0;
-    while write_size < size as usize {
-        let mut buf = [0; 100];
-        let readed = file.read(&mut buf)?;
-        let writed = stream.write(&buf[0..readed])?;
-        write_size += writed;
+    while write_size < size {
+        let to_read = cmp::min(size - write_size, BUFFER_SIZE as u64) as usize;
+        let readed = reader.read(&mut buf[0..to_read])?;
+        if readed == 0 {
+            bail!(io::Error::new(io::ErrorKind::UnexpectedEof, "file is shorter than expected"));
+        }
+        transport.write_data(&buf[0..readed])?;
+        hasher.update(&buf[0..readed]);
+        write_size += readed as u64;
+    }
+    if transport.checksum() {
+        let mut digest = [0; DIGEST_SIZE];
+        digest.copy_from_slice(hasher.finalize().as_slice());
+        transport.write_digest(&digest)?;
     }
     Ok(())
 }
 
 /// Send list of files to stream
-pub fn send_list_file<W: Write>(stream: &mut W, list: Vec<String>) -> Result<()> {
-    stream.write(&u64_as_bytes(list.len() as u64))?;
+pub fn send_list_file<S: Read + Write>(transport: &mut Transport<S>, list: Vec<String>) -> Result<()> {
+    transport.write_size(list.len() as u64)?;
     for file in list {
-        let to_send = format!("{}\n", file);
-        stream.write(to_send.as_bytes())?;
+        transport.write_line(&file)?;
     }
     Ok(())
 }
@@ -103,34 +518,57 @@ pub fn canonicalize<P: AsRef<Path>>(path: P) -> String {
     path.iter().map(|s| format!("/{}", s)).collect::<String>()
 }
 
-/// Receive size of file or else that will be sent
-fn read_size<R: Read>(stream: &mut R) -> Result<u64> {
-    let mut buf = [0; 8];
-    stream.read(&mut buf)?;
-    let size: u64 = bytes_to_u64(buf);
-    Ok(size)
-}
-
 /// Read line from stream
 pub fn read_line<R: Read>(stream: &mut R, buf: &mut String) -> Result<()> {
     let mut b = [0];
+    let mut bytes = Vec::new();
     loop {
-        stream.read(&mut b)?;
-        let c = b[0] as char;
-        if c == '\n' {
-            return Ok(());
+        stream.read_exact(&mut b)?;
+        if b[0] == b'\n' {
+            break;
         }
-        buf.push(c);
-        ::std::thread::sleep(::std::time::Duration::from_millis(50));
+        bytes.push(b[0]);
     }
+    match String::from_utf8(bytes) {
+        Ok(line) => buf.push_str(&line),
+        Err(e) => {
+            let line = String::from_utf8_lossy(e.as_bytes()).into_owned();
+            bail!(ErrorKind::InvalidCommand(line));
+        }
+    }
+    Ok(())
 }
 
-/// Convert an u64 to an array of u8
+/// Convert an u64 to an array of u8, in the native byte order of the text encoding
 pub fn u64_as_bytes(num: u64) -> [u8; 8] {
-    unsafe { mem::transmute::<u64, [u8; 8]>(num) }
+    num.to_ne_bytes()
 }
 
-/// Convert an array of u8 to u64
+/// Convert an array of u8 in the native byte order of the text encoding to u64
 pub fn bytes_to_u64(arr: [u8; 8]) -> u64 {
-    unsafe { mem::transmute::<[u8; 8], u64>(arr) }
+    u64::from_ne_bytes(arr)
+}
+
+/// Encode bytes as lowercase hexadecimal
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decode hexadecimal string, returning `None` if it isn't valid
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Compare two byte strings in a time which doesn't depend on their content
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
 }
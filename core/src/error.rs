@@ -20,6 +20,14 @@ error_chain!{
             description("parsed command is invalid")
             display("invalid command: {}", c)
         }
+        InvalidResponse(r: String) {
+            description("received response is invalid")
+            display("invalid response: {}", r)
+        }
+        InvalidFrame(reason: String) {
+            description("received frame is invalid")
+            display("invalid frame: {}", reason)
+        }
         InvalidLogin {
             description("user name or password is invalid")
             display("user name or password is invalid")
@@ -28,9 +36,61 @@ error_chain!{
             description("client is not logged in server")
             display("client is not logged in server")
         }
-        InvalidUserDB {
+        Disconnected {
+            description("server ended the connection")
+            display("server ended the connection")
+        }
+        UnsupportedVersion(client: u32, server: u32) {
+            description("protocol version is not supported")
+            display("unsupported protocol version: client speaks {}, server speaks {}", client, server)
+        }
+        ChecksumMismatch {
+            description("checksum of transferred data does not match")
+            display("checksum of transferred data does not match")
+        }
+        InvalidOffset {
+            description("offset is past the end of file")
+            display("offset is past the end of file")
+        }
+        UnexpectedStatus(status: ::types::Status) {
+            description("server answered with a negative status")
+            display("server answered with a negative status: {:?}", status)
+        }
+        PermissionDenied {
+            description("path is outside of user root or operation is not allowed")
+            display("permission denied: path is outside of user root or operation is not allowed")
+        }
+        Tls(reason: String) {
+            description("TLS error")
+            display("TLS error: {}", reason)
+        }
+        InvalidPermissions(p: String) {
+            description("permissions are invalid")
+            display("invalid permissions: {}", p)
+        }
+        UserExists(user: String) {
+            description("user already exists")
+            display("user already exists: {}", user)
+        }
+        UserUnknown(user: String) {
+            description("user doesn't exist")
+            display("user doesn't exist: {}", user)
+        }
+        InvalidUserName(user: String) {
+            description("user name is invalid")
+            display("invalid user name: {:?}", user)
+        }
+        Unsupported {
+            description("operation is not supported by server")
+            display("operation is not supported by server")
+        }
+        InvalidUserRecord {
+            description("record of user is invalid")
+            display("record of user is invalid")
+        }
+        InvalidUserDB(line: usize) {
             description("provided user database is invalid")
-            display("provided user database is invalid")
+            display("provided user database is invalid at line {}", line)
         }
     }
 }
@@ -2,16 +2,39 @@
 #![deny(missing_docs)]
 
 extern crate app_dirs;
+extern crate base64;
+extern crate bcrypt;
 #[macro_use]
 extern crate error_chain;
+extern crate getrandom;
+extern crate hmac;
+extern crate md5;
+extern crate pbkdf2;
+#[cfg(feature = "tls")]
+extern crate rustls;
+#[cfg(feature = "tls")]
+extern crate rustls_pemfile;
+extern crate sha1;
+extern crate sha2;
+#[cfg(feature = "async")]
+extern crate tokio;
+#[cfg(feature = "tls")]
+extern crate webpki_roots;
 
+#[cfg(feature = "async")]
+pub mod async_client;
 pub mod client;
 pub mod error;
 pub mod server;
 pub mod sync;
+#[cfg(feature = "tls")]
+pub mod tls;
 pub mod types;
 
+#[cfg(feature = "async")]
+mod bridge;
 mod common;
+mod scram;
 
 /// App information
 pub const APP_INFO: app_dirs::AppInfo = app_dirs::AppInfo {
@@ -19,5 +42,11 @@ pub const APP_INFO: app_dirs::AppInfo = app_dirs::AppInfo {
     author: "notkild",
 };
 
+/// Protocol version spoken by this version of soft
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Oldest protocol version still understood by this version of soft
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
 /// Default port used by soft
 pub const DEFAULT_PORT: u16 = 9045;
@@ -0,0 +1,88 @@
+//! Challenge-response authentication in the style of SCRAM-SHA-256
+//!
+//! Client sends `AUTH <user> <client nonce>`, server answers with the full nonce, the salt
+//! and the number of iterations used to hash the password of user. Client then proves it
+//! knows the password with `PROOF <proof>` and server answers with its own signature,
+//! proving it knows the password hash. The password itself never crosses the wire.
+use error::*;
+use hmac::{Hmac, Mac};
+use pbkdf2::pbkdf2_hmac;
+use sha2::{Digest, Sha256};
+
+/// Size of random nonces
+const NONCE_SIZE: usize = 16;
+
+/// Size of keys
+pub const KEY_SIZE: usize = 32;
+
+/// Generate a random nonce
+pub fn nonce() -> Result<String> {
+    Ok(::common::to_hex(&random_bytes(NONCE_SIZE)?))
+}
+
+/// Generate random bytes
+pub fn random_bytes(len: usize) -> Result<Vec<u8>> {
+    let mut bytes = vec![0; len];
+    getrandom::getrandom(&mut bytes).map_err(|e| Error::from(e.to_string()))?;
+    Ok(bytes)
+}
+
+/// Hash password with salt
+pub fn salted_password(pass: &str, salt: &[u8], iterations: u32) -> Vec<u8> {
+    let mut salted = vec![0; KEY_SIZE];
+    pbkdf2_hmac::<Sha256>(pass.as_bytes(), salt, iterations, &mut salted);
+    salted
+}
+
+/// Key proving client knows password
+pub fn client_key(salted_password: &[u8]) -> Vec<u8> {
+    hmac(salted_password, b"Client Key")
+}
+
+/// Key proving server knows password hash
+pub fn server_key(salted_password: &[u8]) -> Vec<u8> {
+    hmac(salted_password, b"Server Key")
+}
+
+/// Key kept by server to check client proofs
+pub fn stored_key(client_key: &[u8]) -> Vec<u8> {
+    Sha256::digest(client_key).to_vec()
+}
+
+/// Message signed by both sides, binding the exchange
+pub fn auth_message(user: &str,
+                    client_nonce: &str,
+                    nonce: &str,
+                    salt: &str,
+                    iterations: u32)
+                    -> String {
+    format!("{},{},{},{},{}", user, client_nonce, nonce, salt, iterations)
+}
+
+/// Proof sent by client
+pub fn client_proof(client_key: &[u8], stored_key: &[u8], auth_message: &str) -> Vec<u8> {
+    let signature = hmac(stored_key, auth_message.as_bytes());
+    client_key.iter().zip(signature).map(|(k, s)| k ^ s).collect()
+}
+
+/// Check proof sent by client
+pub fn verify_proof(stored_key: &[u8], auth_message: &str, proof: &[u8]) -> bool {
+    let signature = hmac(stored_key, auth_message.as_bytes());
+    if proof.len() != signature.len() {
+        return false;
+    }
+    let client_key = proof.iter().zip(signature).map(|(p, s)| p ^ s).collect::<Vec<u8>>();
+    ::common::constant_time_eq(&self::stored_key(&client_key), stored_key)
+}
+
+/// Signature sent by server
+pub fn server_signature(server_key: &[u8], auth_message: &str) -> Vec<u8> {
+    hmac(server_key, auth_message.as_bytes())
+}
+
+/// Compute HMAC-SHA256 of data
+pub fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
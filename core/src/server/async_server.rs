@@ -0,0 +1,241 @@
+//! Soft server for tokio streams
+//!
+//! Connections waiting for a command are tasks which don't hold any thread. Once a command
+//! has been wholly received, it is handled like by the blocking server, on the blocking
+//! threads of tokio, so that storage and authenticator may block.
+//!
+//! A command holds its blocking thread until it ends, file transfers included, so only idle
+//! connections are cheap: running transfers are limited by the size of the blocking pool.
+use bridge::Bridge;
+use error::*;
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use super::ConnectionCounts;
+use super::auth::Authenticator;
+use super::connection::SoftConnection;
+use super::storage::{LocalStorage, Storage};
+use super::users::Users;
+use super::{ACCEPT_BACKOFF, is_transient};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::runtime::Handle;
+use tokio::task::{self, JoinHandle};
+use tokio::time::{self, Sleep};
+
+/// Soft server handling connections as tokio tasks
+///
+/// Made from a configured server with `SoftServer::into_async`, its methods must be called
+/// from a tokio runtime. There is no shutdown handle like the one of `SoftServer`: dropping
+/// the future of `serve` stops accepting clients, but leaves running connections alone.
+///
+/// ```no_run
+/// # extern crate soft_core;
+/// # extern crate tokio;
+/// use soft_core::server::SoftServer;
+/// use tokio::net::TcpListener;
+///
+/// let runtime = tokio::runtime::Runtime::new().unwrap();
+/// let server = SoftServer::new("server", None, true).unwrap().into_async();
+/// let listener = runtime.block_on(TcpListener::bind("127.0.0.1:9045")).unwrap();
+/// runtime.block_on(server.serve(listener)).unwrap();
+/// ```
+pub struct AsyncSoftServer<St: Storage = LocalStorage, A: Authenticator = Users> {
+    authenticator: Arc<A>,
+    storage: Arc<St>,
+    home: String,
+    allow_anonymous: bool,
+    counts: Arc<Mutex<ConnectionCounts>>,
+}
+
+impl<St: Storage, A: Authenticator> AsyncSoftServer<St, A> {
+    /// Initialize a new server from its parts
+    pub(super) fn from_parts(authenticator: Arc<A>,
+                             storage: Arc<St>,
+                             home: String,
+                             allow_anonymous: bool)
+                             -> AsyncSoftServer<St, A> {
+        AsyncSoftServer {
+            authenticator,
+            storage,
+            home,
+            allow_anonymous,
+            counts: Arc::new(Mutex::new(ConnectionCounts::default())),
+        }
+    }
+
+    /// Add a new connection to server, run by a new task
+    pub fn new_connection<S>(&self, stream: S)
+        where S: AsyncRead + AsyncWrite + Unpin + Send + 'static
+    {
+        let stream = Bridge::new(stream, Handle::current());
+        let connection = SoftConnection::new(stream,
+                                             self.authenticator.clone(),
+                                             self.storage.clone(),
+                                             self.home.clone(),
+                                             self.allow_anonymous,
+                                             None);
+        self.counts.lock().unwrap().active += 1;
+        task::spawn(ConnectionTask {
+            state: State::Idle(Box::new(connection)),
+            counts: self.counts.clone(),
+        });
+    }
+
+    /// Accept clients of listener forever
+    ///
+    /// Errors of accept don't stop the server, which waits a bit before accepting again
+    /// unless they only concern the client being accepted.
+    pub fn serve(self, listener: TcpListener) -> impl Future<Output = Result<()>> {
+        Serve {
+            server: self,
+            listener,
+            backoff: None,
+        }
+    }
+
+    /// Get counts of running and ended connections
+    pub fn connection_counts(&self) -> ConnectionCounts {
+        *self.counts.lock().unwrap()
+    }
+
+    /// Get number of running connections
+    pub fn active_connections(&self) -> usize {
+        self.connection_counts().active
+    }
+
+    /// Get authenticator checking users
+    pub fn get_authenticator(&self) -> Arc<A> {
+        self.authenticator.clone()
+    }
+
+    /// Get storage keeping files
+    pub fn get_storage(&self) -> Arc<St> {
+        self.storage.clone()
+    }
+}
+
+impl<St: Storage, A: Authenticator> Clone for AsyncSoftServer<St, A> {
+    /// Get another handle to server, sharing its connection counts
+    fn clone(&self) -> AsyncSoftServer<St, A> {
+        AsyncSoftServer {
+            authenticator: self.authenticator.clone(),
+            storage: self.storage.clone(),
+            home: self.home.clone(),
+            allow_anonymous: self.allow_anonymous,
+            counts: self.counts.clone(),
+        }
+    }
+}
+
+/// Connection handled by a blocking thread, with result of its last command
+type Step<S, St, A> = (Box<SoftConnection<Bridge<S>, St, A>>, Result<bool>);
+
+/// State of a connection task
+enum State<S: AsyncRead + AsyncWrite + Unpin, St: Storage, A: Authenticator> {
+    /// Waiting for a command
+    Idle(Box<SoftConnection<Bridge<S>, St, A>>),
+    /// Handling a command
+    Running(JoinHandle<Step<S, St, A>>),
+    /// Ended
+    Done,
+}
+
+/// Task running a connection
+struct ConnectionTask<S: AsyncRead + AsyncWrite + Unpin, St: Storage, A: Authenticator> {
+    state: State<S, St, A>,
+    counts: Arc<Mutex<ConnectionCounts>>,
+}
+
+impl<S, St, A> ConnectionTask<S, St, A>
+    where S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+          St: Storage,
+          A: Authenticator
+{
+    /// Count connection as ended
+    fn finish(&mut self, succeeded: bool) -> Poll<()> {
+        let mut counts = self.counts.lock().unwrap();
+        counts.active -= 1;
+        counts.finished += 1;
+        if !succeeded {
+            counts.failed += 1;
+        }
+        Poll::Ready(())
+    }
+}
+
+impl<S, St, A> Future for ConnectionTask<S, St, A>
+    where S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+          St: Storage,
+          A: Authenticator
+{
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        loop {
+            match mem::replace(&mut self.state, State::Done) {
+                State::Idle(mut connection) => {
+                    let encoding = connection.encoding();
+                    match connection.stream_mut().poll_command(cx, encoding) {
+                        Poll::Ready(Ok(true)) => {
+                            self.state = State::Running(task::spawn_blocking(move || {
+                                let result = connection.step();
+                                (connection, result)
+                            }));
+                        }
+                        Poll::Ready(_) => return self.finish(false),
+                        Poll::Pending => {
+                            self.state = State::Idle(connection);
+                            return Poll::Pending;
+                        }
+                    }
+                }
+                State::Running(mut handle) => {
+                    match Pin::new(&mut handle).poll(cx) {
+                        Poll::Ready(Ok((connection, Ok(true)))) => {
+                            self.state = State::Idle(connection);
+                        }
+                        Poll::Ready(Ok((_, Ok(false)))) => return self.finish(true),
+                        Poll::Ready(_) => return self.finish(false),
+                        Poll::Pending => {
+                            self.state = State::Running(handle);
+                            return Poll::Pending;
+                        }
+                    }
+                }
+                State::Done => return Poll::Ready(()),
+            }
+        }
+    }
+}
+
+/// Future accepting clients of a listener
+struct Serve<St: Storage, A: Authenticator> {
+    server: AsyncSoftServer<St, A>,
+    listener: TcpListener,
+    /// Delay after a failure of listener
+    backoff: Option<Pin<Box<Sleep>>>,
+}
+
+impl<St: Storage, A: Authenticator> Future for Serve<St, A> {
+    type Output = Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
+        loop {
+            if let Some(mut backoff) = self.backoff.take() {
+                if backoff.as_mut().poll(cx).is_pending() {
+                    self.backoff = Some(backoff);
+                    return Poll::Pending;
+                }
+            }
+            match self.listener.poll_accept(cx) {
+                Poll::Ready(Ok((stream, _))) => self.server.new_connection(stream),
+                Poll::Ready(Err(ref e)) if is_transient(e) => {}
+                Poll::Ready(Err(_)) => self.backoff = Some(Box::pin(time::sleep(ACCEPT_BACKOFF))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Authentication against an Apache htpasswd file
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use error::*;
+use md5::Md5;
+use sha1::{Digest, Sha1};
+use std::fs;
+use std::path::{Path, PathBuf};
+use super::{Authenticator, Identity};
+
+/// Characters of the base 64 encoding used by crypt
+const CRYPT_BASE64: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Users of an htpasswd file, as written by Apache `htpasswd`
+///
+/// Lines are `user:hash`, where hash is bcrypt (`htpasswd -B`), Apache MD5 (`-m`, the
+/// default) or SHA-1 (`-s`). Plaintext and crypt(3) hashes can't be told apart, so both
+/// are refused. File is read again on every login, so that changes apply at once.
+pub struct HtpasswdFile {
+    path: PathBuf,
+}
+
+impl HtpasswdFile {
+    /// Use htpasswd file at path, failing if it can't be read
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<HtpasswdFile> {
+        fs::File::open(path.as_ref())?;
+        Ok(HtpasswdFile { path: path.as_ref().to_path_buf() })
+    }
+
+    /// Get path of htpasswd file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Find password hash of user
+    fn hash(&self, user: &str) -> Result<Option<String>> {
+        let content = fs::read_to_string(&self.path)?;
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some((name, hash)) = line.split_once(':') {
+                if name == user {
+                    return Ok(Some(hash.to_owned()));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Authenticator for HtpasswdFile {
+    fn authenticate(&self, user: &str, pass: &str) -> Option<Identity> {
+        match self.hash(user) {
+            Ok(Some(ref hash)) if verify(hash, pass) => Some(Identity::new(user)),
+            _ => None,
+        }
+    }
+}
+
+/// Check if pass matches an htpasswd hash
+fn verify(hash: &str, pass: &str) -> bool {
+    if hash.starts_with("$2") {
+        bcrypt::verify(pass, hash).unwrap_or(false)
+    } else if let Some(rest) = hash.strip_prefix("$apr1$") {
+        let salt = rest.split('$').next().unwrap_or("");
+        ::common::constant_time_eq(apr1(pass, salt).as_bytes(), hash.as_bytes())
+    } else if let Some(digest) = hash.strip_prefix("{SHA}") {
+        let expected = BASE64.encode(Sha1::digest(pass.as_bytes()));
+        ::common::constant_time_eq(expected.as_bytes(), digest.as_bytes())
+    } else {
+        false
+    }
+}
+
+/// Hash pass with salt using the Apache variant of MD5 crypt
+fn apr1(pass: &str, salt: &str) -> String {
+    let pass = pass.as_bytes();
+    let salt = &salt.as_bytes()[..salt.len().min(8)];
+    let alternate = Md5::new().chain_update(pass).chain_update(salt).chain_update(pass).finalize();
+    let mut context = Md5::new();
+    context.update(pass);
+    context.update(b"$apr1$");
+    context.update(salt);
+    for chunk in pass.chunks(16) {
+        context.update(&alternate[..chunk.len()]);
+    }
+    let mut i = pass.len();
+    while i > 0 {
+        if i & 1 == 1 {
+            context.update([0]);
+        } else {
+            context.update(&pass[..1]);
+        }
+        i >>= 1;
+    }
+    let mut digest = context.finalize();
+    for i in 0..1000 {
+        let mut context = Md5::new();
+        if i & 1 == 1 {
+            context.update(pass);
+        } else {
+            context.update(digest);
+        }
+        if i % 3 != 0 {
+            context.update(salt);
+        }
+        if i % 7 != 0 {
+            context.update(pass);
+        }
+        if i & 1 == 1 {
+            context.update(digest);
+        } else {
+            context.update(pass);
+        }
+        digest = context.finalize();
+    }
+    let mut hash = format!("$apr1${}$", String::from_utf8_lossy(salt));
+    for &(a, b, c) in &[(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)] {
+        let value = (digest[a] as u32) << 16 | (digest[b] as u32) << 8 | digest[c] as u32;
+        push_crypt_base64(&mut hash, value, 4);
+    }
+    push_crypt_base64(&mut hash, digest[11] as u32, 2);
+    hash
+}
+
+/// Append the n low sextets of value, least significant first
+fn push_crypt_base64(s: &mut String, mut value: u32, n: usize) {
+    for _ in 0..n {
+        s.push(CRYPT_BASE64[(value & 0x3f) as usize] as char);
+        value >>= 6;
+    }
+}
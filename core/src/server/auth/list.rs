@@ -0,0 +1,92 @@
+//! Authentication against a static list of users
+use error::*;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use super::{Authenticator, Identity, Permissions};
+use super::super::users::{KeyCache, Password, ScramKeys};
+
+/// Fixed list of users, usually read from configuration
+///
+/// ```
+/// use soft_core::server::auth::StaticUsers;
+///
+/// let users = StaticUsers::parse("# user password [home [permissions]]\n\
+///                                 alice secret\n\
+///                                 bob hunter2 /shared rl\n").unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct StaticUsers {
+    users: HashMap<String, (Password, Identity)>,
+    keys: KeyCache,
+}
+
+impl StaticUsers {
+    /// Create an empty list
+    pub fn new() -> StaticUsers {
+        StaticUsers::default()
+    }
+
+    /// Parse a list of users
+    ///
+    /// Each line is `user password [home [permissions]]`, where password is plaintext or a
+    /// record of the user database. Empty lines and lines starting with `#` are ignored.
+    pub fn parse(config: &str) -> Result<StaticUsers> {
+        let mut list = StaticUsers::new();
+        for (i, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (identity, password) = parse_line(line)
+                .chain_err(|| ErrorKind::InvalidUserDB(i + 1))?;
+            list.add(identity, password);
+        }
+        Ok(list)
+    }
+
+    /// Read list of users from a file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<StaticUsers> {
+        StaticUsers::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Add a user, replacing any user with the same name
+    pub fn add(&mut self, identity: Identity, password: Password) {
+        self.users.insert(identity.user.clone(), (password, identity));
+    }
+}
+
+/// Parse a line of list into identity and password of user
+fn parse_line(line: &str) -> Result<(Identity, Password)> {
+    let words = line.split_whitespace().collect::<Vec<&str>>();
+    if words.len() < 2 || words.len() > 4 {
+        bail!(ErrorKind::InvalidUserRecord);
+    }
+    let mut identity = Identity::new(words[0]);
+    identity.home = words.get(2).map(|home| home.to_string());
+    if let Some(permissions) = words.get(3) {
+        identity.permissions = Permissions::try_from(permissions)?;
+    }
+    Ok((identity, Password::try_from(words[1])?))
+}
+
+impl Authenticator for StaticUsers {
+    fn authenticate(&self, user: &str, pass: &str) -> Option<Identity> {
+        match self.users.get(user) {
+            Some((password, identity)) if password.verify(pass) => Some(identity.clone()),
+            _ => None,
+        }
+    }
+
+    fn supports_scram(&self) -> bool {
+        true
+    }
+
+    fn scram_keys(&self, user: &str) -> Option<ScramKeys> {
+        self.users.get(user).and_then(|(password, _)| self.keys.scram_keys(user, password))
+    }
+
+    fn identity(&self, user: &str) -> Option<Identity> {
+        self.users.get(user).map(|(_, identity)| identity.clone())
+    }
+}
@@ -0,0 +1,89 @@
+//! Authentication backends used by soft server to check users
+mod htpasswd;
+mod list;
+
+pub use self::htpasswd::HtpasswdFile;
+pub use self::list::StaticUsers;
+
+pub use types::Permissions;
+
+use error::*;
+use super::users::ScramKeys;
+
+/// Identity of an authenticated user
+#[derive(Clone, Debug, PartialEq)]
+pub struct Identity {
+    /// Name of user
+    pub user: String,
+    /// Home directory in storage, or `None` to follow the template of the server
+    pub home: Option<String>,
+    /// Operations allowed to user
+    pub permissions: Permissions,
+}
+
+impl Identity {
+    /// Create identity of user with default home and all permissions
+    pub fn new(user: &str) -> Identity {
+        Identity {
+            user: user.to_owned(),
+            home: None,
+            permissions: Permissions::all(),
+        }
+    }
+
+    /// Create identity of anonymous user, who can only read
+    pub fn anonymous() -> Identity {
+        Identity {
+            permissions: Permissions::read_only(),
+            ..Identity::new("anonymous")
+        }
+    }
+}
+
+/// Backend checking credentials of users
+///
+/// Backends keeping SCRAM keys of users also support challenge-response login, where
+/// passwords never cross the wire. Others only support plain `LOGIN`. Backends which can
+/// be changed also support management of users, others fail with `ErrorKind::Unsupported`.
+pub trait Authenticator: Send + Sync + 'static {
+    /// Check password of user, returning its identity if valid
+    fn authenticate(&self, user: &str, pass: &str) -> Option<Identity>;
+
+    /// Check if backend supports challenge-response login
+    fn supports_scram(&self) -> bool {
+        false
+    }
+
+    /// Get keys to authenticate user with a challenge
+    fn scram_keys(&self, _user: &str) -> Option<ScramKeys> {
+        None
+    }
+
+    /// Get identity of a user who answered a challenge
+    fn identity(&self, _user: &str) -> Option<Identity> {
+        None
+    }
+
+    /// Replace outdated password record of user by keys which authenticated it
+    fn upgrade(&self, _user: &str, _keys: ScramKeys) {}
+
+    /// Add a user, failing with `ErrorKind::UserExists` if there is already one
+    fn add_user(&self, _user: &str, _pass: &str, _permissions: Permissions) -> Result<()> {
+        bail!(ErrorKind::Unsupported)
+    }
+
+    /// Remove a user, failing with `ErrorKind::UserUnknown` if there is none
+    fn remove_user(&self, _user: &str) -> Result<()> {
+        bail!(ErrorKind::Unsupported)
+    }
+
+    /// Change password of a user, failing with `ErrorKind::UserUnknown` if there is none
+    fn set_password(&self, _user: &str, _pass: &str) -> Result<()> {
+        bail!(ErrorKind::Unsupported)
+    }
+
+    /// List identities of all users, sorted by name
+    fn list_users(&self) -> Result<Vec<Identity>> {
+        bail!(ErrorKind::Unsupported)
+    }
+}
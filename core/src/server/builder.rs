@@ -0,0 +1,144 @@
+//! Builder to configure a soft server
+use APP_INFO;
+use app_dirs::{AppDataType, app_dir, app_root};
+use error::*;
+use std::path::{Path, PathBuf};
+use super::SoftServer;
+use super::auth::Authenticator;
+use super::storage::{LocalStorage, Storage};
+use super::users::Users;
+
+/// Default template of user home directories
+pub const DEFAULT_HOME: &str = "/users/{user}";
+
+/// Builder of soft server
+///
+/// ```
+/// use soft_core::server::SoftServerBuilder;
+///
+/// let root = std::env::temp_dir().join("soft_builder_doc");
+/// let server = SoftServerBuilder::new()
+///     .root(&root)
+///     .user_db(root.join("users.db"))
+///     .home("/home/{user}")
+///     .max_threads(4)
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct SoftServerBuilder {
+    root: Option<PathBuf>,
+    user_db: Option<PathBuf>,
+    home: String,
+    max_threads: Option<usize>,
+    allow_anonymous: bool,
+}
+
+impl Default for SoftServerBuilder {
+    fn default() -> SoftServerBuilder {
+        SoftServerBuilder::new()
+    }
+}
+
+impl SoftServerBuilder {
+    /// Create a builder with default configuration
+    ///
+    /// Files are kept in the user data directory, users are kept in memory, homes follow
+    /// `DEFAULT_HOME` and anonymous users are refused.
+    pub fn new() -> SoftServerBuilder {
+        SoftServerBuilder {
+            root: None,
+            user_db: None,
+            home: DEFAULT_HOME.to_string(),
+            max_threads: None,
+            allow_anonymous: false,
+        }
+    }
+
+    /// Create a builder keeping users in the `db/<name>` user data directory
+    pub fn named(name: &str) -> Result<SoftServerBuilder> {
+        let db_dir = app_dir(AppDataType::UserData,
+                             &APP_INFO,
+                             format!("db/{}", name).as_str())?;
+        Ok(SoftServerBuilder::new().user_db(db_dir.join("users.db")))
+    }
+
+    /// Set directory holding files served by a local storage
+    pub fn root<P: AsRef<Path>>(mut self, root: P) -> SoftServerBuilder {
+        self.root = Some(root.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set file keeping database of users
+    pub fn user_db<P: AsRef<Path>>(mut self, path: P) -> SoftServerBuilder {
+        self.user_db = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set template of user home directories, where `{user}` is replaced by user name
+    ///
+    /// Homes are paths of the storage, use `/` to serve the whole storage to everyone.
+    pub fn home(mut self, template: &str) -> SoftServerBuilder {
+        self.home = template.to_string();
+        self
+    }
+
+    /// Set maximum number of connections handled at once
+    pub fn max_threads(mut self, max_threads: usize) -> SoftServerBuilder {
+        self.max_threads = Some(max_threads);
+        self
+    }
+
+    /// Allow users to login as `anonymous` without password, with read-only permissions
+    pub fn allow_anonymous(mut self, allow_anonymous: bool) -> SoftServerBuilder {
+        self.allow_anonymous = allow_anonymous;
+        self
+    }
+
+    /// Build a server keeping files on local disk, under root directory
+    pub fn build(self) -> Result<SoftServer<LocalStorage>> {
+        let storage = self.local_storage()?;
+        self.build_with(storage)
+    }
+
+    /// Build a server keeping files in storage, root directory is ignored
+    pub fn build_with<St: Storage>(self, storage: St) -> Result<SoftServer<St>> {
+        let users = match self.user_db {
+            Some(ref path) => Users::load_file(path)?,
+            None => Users::new(),
+        };
+        Ok(self.build_from(storage, users))
+    }
+
+    /// Build a server keeping files on local disk and checking users with authenticator
+    ///
+    /// User database is ignored.
+    pub fn build_with_authenticator<A: Authenticator>(self,
+                                                      authenticator: A)
+                                                      -> Result<SoftServer<LocalStorage, A>> {
+        let storage = self.local_storage()?;
+        Ok(self.build_from(storage, authenticator))
+    }
+
+    /// Build a server keeping files in storage and checking users with authenticator
+    ///
+    /// Root directory and user database are ignored.
+    pub fn build_from<St: Storage, A: Authenticator>(self,
+                                                     storage: St,
+                                                     authenticator: A)
+                                                     -> SoftServer<St, A> {
+        SoftServer::from_parts(authenticator,
+                               storage,
+                               self.home,
+                               self.max_threads,
+                               self.allow_anonymous)
+    }
+
+    /// Open local storage in root directory
+    fn local_storage(&self) -> Result<LocalStorage> {
+        match self.root {
+            Some(ref root) => LocalStorage::new(root),
+            None => LocalStorage::new(app_root(AppDataType::UserData, &APP_INFO)?),
+        }
+    }
+}
@@ -1,223 +1,778 @@
-use APP_INFO;
-use app_dirs::{AppDataType, app_dir};
+use {MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use common::{Encoding, Transport};
 use error::*;
-use std::fs::{self, File};
-use std::io::{Read, Write};
-use std::path::PathBuf;
-use std::sync::mpsc;
+use std::io::{self, Read, Write};
+use std::process;
 use std::sync::Arc;
-use super::users::Users;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use super::storage::{PARTIAL_SUFFIX, Storage, UPLOAD_PREFIX};
+use super::auth::{Authenticator, Identity, Permissions};
+use super::shutdown::Watch;
+use super::users::ScramKeys;
+use scram;
 use types::*;
 
-pub struct SoftConnection<S: Read + Write> {
-    root: Option<PathBuf>,
+/// Challenge-response login waiting for client proof
+struct ScramState {
+    user: String,
+    keys: ScramKeys,
+    auth_message: String,
+    known: bool,
+}
+
+pub struct SoftConnection<S: Read + Write, St: Storage, A: Authenticator> {
+    home_template: String,
+    user: String,
+    home: Option<String>,
+    real_home: String,
     cwd: String,
-    stream: S,
-    sender: mpsc::Sender<u8>,
-    users: Arc<Users>,
+    permissions: Permissions,
+    transport: Transport<S>,
+    authenticator: Arc<A>,
+    storage: Arc<St>,
     allow_anonymous: bool,
+    scram: Option<ScramState>,
+    watch: Option<Arc<Watch>>,
 }
 
-impl<S: Read + Write> SoftConnection<S> {
+impl<S: Read + Write, St: Storage, A: Authenticator> SoftConnection<S, St, A> {
     pub fn new(stream: S,
-               sender: mpsc::Sender<u8>,
-               users: Arc<Users>,
-               allow_anonymous: bool)
-               -> SoftConnection<S> {
+               authenticator: Arc<A>,
+               storage: Arc<St>,
+               home_template: String,
+               allow_anonymous: bool,
+               watch: Option<Arc<Watch>>)
+               -> SoftConnection<S, St, A> {
         SoftConnection {
-            root: None,
+            home_template,
+            user: String::new(),
+            home: None,
+            real_home: String::new(),
             cwd: String::new(),
-            stream: stream,
-            sender: sender,
-            users: users,
-            allow_anonymous: allow_anonymous,
+            permissions: Permissions::none(),
+            transport: Transport::new(stream),
+            authenticator,
+            storage,
+            allow_anonymous,
+            scram: None,
+            watch,
         }
     }
 
     /// Run handler for this connection
     pub fn run(&mut self) -> Result<()> {
-        loop {
-            let command = self.read_command()?;
-            match command {
-                Command::Login(_, _) |
-                Command::Presence |
-                Command::Exit => {}
-                _ => {
-                    if self.root.is_none() {
-                        self.write_status(Status::NotConnected)?;
-                        continue;
-                    }
+        while self.step()? {}
+        Ok(())
+    }
+
+    /// Read and handle a single command, returning false once client exited
+    ///
+    /// Once server is shutting down, client is told it is disconnected instead.
+    pub fn step(&mut self) -> Result<bool> {
+        self.set_idle(true);
+        let command = if self.is_stopping() {
+            None
+        } else {
+            Some(self.read_command())
+        };
+        self.set_idle(false);
+        match command {
+            Some(Ok(command)) => self.handle(command),
+            // Server closed input of idle connection to stop it
+            Some(Err(_)) if self.is_stopping() => self.disconnect(),
+            Some(Err(e)) => Err(e),
+            None => self.disconnect(),
+        }
+    }
+
+    /// Get encoding of commands read from client
+    #[cfg(feature = "async")]
+    pub fn encoding(&self) -> Encoding {
+        self.transport.encoding()
+    }
+
+    /// Get stream connected to client
+    #[cfg(feature = "async")]
+    pub fn stream_mut(&mut self) -> &mut S {
+        self.transport.get_mut()
+    }
+
+    /// Handle a command, returning false once client exited
+    fn handle(&mut self, command: Command) -> Result<bool> {
+        match command {
+            Command::Login(_, _) |
+            Command::Auth(_, _) |
+            Command::Proof(_) |
+            Command::Hello(_, _) |
+            Command::Presence |
+            Command::Exit => {}
+            _ => {
+                if self.home.is_none() {
+                    self.write_status(Status::NotConnected)?;
+                    return Ok(true);
                 }
             }
-            match command {
-                Command::Login(u, p) => {
-                    if !(self.allow_anonymous && &u == "anonymous") &&
-                       !self.users.is_valid(&u, &p) {
+        }
+        if !self.is_allowed(&command) {
+            self.write_status(Status::PermissionDenied)?;
+            return Ok(true);
+        }
+        match command {
+            Command::Login(u, p) => {
+                let identity = if self.allow_anonymous && u == "anonymous" {
+                    Some(Identity::anonymous())
+                } else {
+                    self.authenticator.authenticate(&u, &p)
+                };
+                let identity = match identity {
+                    Some(identity) => identity,
+                    None => {
                         self.write_status(Status::WrongLogin)?;
-                        continue;
+                        return Ok(true);
                     }
+                };
+                if self.start_session(&identity)? {
                     self.write_status(Status::Connected)?;
-                    self.root = Some(app_dir(AppDataType::UserData,
-                                             &APP_INFO,
-                                             format!("users/{}", u).as_str())?);
-                    self.cwd = "/".to_string();
-                }
-                Command::Get(p) => {
-                    self.write_status(Status::Okay)?;
-                    self.send_file(&p)?;
-                }
-                Command::Put(p) => {
-                    self.write_status(Status::Okay)?;
-                    let path_str = format!("{}/{}",
-                                           self.root.clone().unwrap().display(),
-                                           self.to_server_path(&p));
-                    let path = PathBuf::from(path_str);
-                    let data = self.recv_file()?;
-                    let mut file = File::create(&path)?;
-                    file.write(data.as_slice())?;
-                }
-                Command::List(p) => {
-                    self.write_status(Status::Okay)?;
-                    let path = self.to_server_path(&p);
-                    self.send_list_file(&path)?;
-                }
-                Command::Cwd => {
-                    self.write_status(Status::Okay)?;
-                    self.stream.write(format!("{}\n", self.cwd).as_bytes())?;
-                }
-                Command::Cd(p) => {
-                    self.cwd = self.to_server_path(&p);
-                    self.write_status(Status::Okay)?;
+                } else {
+                    self.write_status(Status::WrongLogin)?;
                 }
-                Command::Mkdir(p) => {
-                    let path = format!("{}/{}/{}",
-                                       self.root.clone().unwrap().display(),
-                                       self.cwd,
-                                       p);
-                    fs::create_dir_all(path)?;
-                    self.write_status(Status::Okay)?;
+            }
+            Command::Auth(u, client_nonce) => self.auth(u, &client_nonce)?,
+            Command::Proof(p) => self.proof(&p)?,
+            Command::Get(p) => self.get(&p, 0)?,
+            Command::GetAt(p, offset) => self.get(&p, offset)?,
+            Command::Put(p) => self.put(&p, 0)?,
+            Command::PutAt(p, offset) => self.put(&p, offset)?,
+            Command::Resume(p) => {
+                let path = match self.resolve(&p, true)? {
+                    Some(path) => path,
+                    None => return Ok(true),
+                };
+                let size = match self.storage.stat(&partial_path(&path)) {
+                    Ok(metadata) if metadata.kind == FileKind::File => metadata.size,
+                    _ => 0,
+                };
+                self.write_status(Status::Okay)?;
+                self.transport.write_size(size)?;
+            }
+            Command::Size(p) => {
+                let path = match self.resolve(&p, true)? {
+                    Some(path) => path,
+                    None => return Ok(true),
+                };
+                let metadata = match self.storage.stat(&path) {
+                    Ok(metadata) => metadata,
+                    Err(_) => {
+                        self.write_status(Status::PathUnknown)?;
+                        return Ok(true);
+                    }
+                };
+                if metadata.kind != FileKind::File {
+                    self.write_status(Status::NotFile)?;
+                    return Ok(true);
                 }
-                Command::Rm(p) => {
-                    let path_str = self.to_root_path(&p);
-                    let path = PathBuf::from(path_str);
-                    if !path.exists() {
+                self.write_status(Status::Okay)?;
+                self.transport.write_size(metadata.size)?;
+            }
+            Command::Stat(p) => {
+                let path = match self.resolve(&p, false)? {
+                    Some(path) => path,
+                    None => return Ok(true),
+                };
+                let metadata = match self.storage.symlink_stat(&path) {
+                    Ok(metadata) => metadata,
+                    Err(_) => {
                         self.write_status(Status::PathUnknown)?;
-                        continue;
+                        return Ok(true);
                     }
-                    if path.is_file() {
-                        fs::remove_file(path)?;
+                };
+                self.write_status(Status::Okay)?;
+                self.write_line(&metadata.to_string())?;
+            }
+            Command::List(p) => {
+                let path = match self.resolve(&p, true)? {
+                    Some(path) => path,
+                    None => return Ok(true),
+                };
+                self.write_status(Status::Okay)?;
+                self.send_list_file(&path)?;
+            }
+            Command::ListLong(p) => {
+                let path = match self.resolve(&p, true)? {
+                    Some(path) => path,
+                    None => return Ok(true),
+                };
+                let entries = match self.list_entries(&path) {
+                    Ok(entries) => entries,
+                    Err(_) => {
+                        self.write_status(Status::PathUnknown)?;
+                        return Ok(true);
+                    }
+                };
+                self.write_status(Status::Okay)?;
+                let list = entries.iter().map(|e| e.to_string()).collect();
+                ::common::send_list_file(&mut self.transport, list)?;
+            }
+            Command::Cwd => {
+                self.write_status(Status::Okay)?;
+                let cwd = quote_arg(&self.cwd);
+                self.write_line(&cwd)?;
+            }
+            Command::Cd(p) => {
+                let path = match self.resolve(&p, true)? {
+                    Some(path) => path,
+                    None => return Ok(true),
+                };
+                self.cwd = self.to_server_path(&path).to_owned();
+                self.write_status(Status::Okay)?;
+            }
+            Command::Mkdir(p) => {
+                let path = match self.resolve(&p, true)? {
+                    Some(path) => path,
+                    None => return Ok(true),
+                };
+                self.storage.mkdir(&path)?;
+                self.write_status(Status::Okay)?;
+            }
+            Command::Rm(p) => {
+                let path = match self.resolve(&p, false)? {
+                    Some(path) => path,
+                    None => return Ok(true),
+                };
+                let metadata = match self.storage.symlink_stat(&path) {
+                    Ok(metadata) => metadata,
+                    Err(_) => {
+                        self.write_status(Status::PathUnknown)?;
+                        return Ok(true);
+                    }
+                };
+                match metadata.kind {
+                    FileKind::File | FileKind::Symlink => {
+                        self.storage.remove_file(&path)?;
                         self.write_status(Status::Okay)?;
-                    } else {
-                        self.write_status(Status::NotFile)?;
                     }
+                    _ => self.write_status(Status::NotFile)?,
                 }
-                Command::Rmdir(p, recursive) => {
-                    let path_str = self.to_root_path(&p);
-                    let path = PathBuf::from(path_str);
-                    if !path.exists() {
+            }
+            Command::Rmdir(p, recursive) => {
+                let path = match self.resolve(&p, true)? {
+                    Some(path) => path,
+                    None => return Ok(true),
+                };
+                let metadata = match self.storage.stat(&path) {
+                    Ok(metadata) => metadata,
+                    Err(_) => {
                         self.write_status(Status::PathUnknown)?;
-                        continue;
+                        return Ok(true);
                     }
-                    if path.is_dir() {
-                        if recursive {
-                            fs::remove_dir_all(path)?;
-                        } else {
-                            fs::remove_dir(path)?;
-                        }
-                        self.write_status(Status::Okay)?;
-                    } else {
-                        self.write_status(Status::NotDir)?;
+                };
+                if metadata.kind != FileKind::Dir {
+                    self.write_status(Status::NotDir)?;
+                    return Ok(true);
+                }
+                match self.storage.remove_dir(&path, recursive) {
+                    Ok(_) => self.write_status(Status::Okay)?,
+                    Err(_) => self.write_status(Status::InvalidPath)?,
+                }
+            }
+            Command::Mv(src, dst) => {
+                let src = match self.resolve(&src, false)? {
+                    Some(path) => path,
+                    None => return Ok(true),
+                };
+                let dst = match self.resolve(&dst, false)? {
+                    Some(path) => path,
+                    None => return Ok(true),
+                };
+                if self.storage.symlink_stat(&src).is_err() {
+                    self.write_status(Status::PathUnknown)?;
+                    return Ok(true);
+                }
+                if is_within(&dst, &src) {
+                    self.write_status(Status::InvalidPath)?;
+                    return Ok(true);
+                }
+                match self.storage.rename(&src, &dst) {
+                    Ok(_) => self.write_status(Status::Okay)?,
+                    Err(_) => self.write_status(Status::InvalidPath)?,
+                }
+            }
+            Command::Cp(src, dst, recursive) => {
+                let src = match self.resolve(&src, true)? {
+                    Some(path) => path,
+                    None => return Ok(true),
+                };
+                let dst = match self.resolve(&dst, true)? {
+                    Some(path) => path,
+                    None => return Ok(true),
+                };
+                let metadata = match self.storage.stat(&src) {
+                    Ok(metadata) => metadata,
+                    Err(_) => {
+                        self.write_status(Status::PathUnknown)?;
+                        return Ok(true);
                     }
+                };
+                if metadata.kind == FileKind::Dir && !recursive {
+                    self.write_status(Status::NotFile)?;
+                    return Ok(true);
                 }
-                Command::Presence => {
-                    self.write_status(Status::Okay)?;
+                if is_within(&dst, &src) {
+                    self.write_status(Status::InvalidPath)?;
+                    return Ok(true);
+                }
+                if self.confine_tree(&src, &mut Vec::new()).is_err() {
+                    self.write_status(Status::PermissionDenied)?;
+                    return Ok(true);
                 }
-                Command::Exit => {
-                    self.write_status(Status::Disconnected)?;
-                    break;
+                match self.storage.copy(&src, &dst) {
+                    Ok(_) => self.write_status(Status::Okay)?,
+                    Err(_) => self.write_status(Status::InvalidPath)?,
                 }
             }
+            Command::Presence => {
+                self.write_status(Status::Okay)?;
+            }
+            Command::Exit => {
+                self.write_status(Status::Disconnected)?;
+                return Ok(false);
+            }
+            Command::UserAdd(u, p, permissions) => {
+                let permissions = permissions.unwrap_or_default();
+                let result = self.authenticator.add_user(&u, &p, permissions);
+                self.write_admin_status(result)?;
+            }
+            Command::UserDel(u) => {
+                let result = self.authenticator.remove_user(&u);
+                self.write_admin_status(result)?;
+            }
+            Command::Passwd(u, p) => {
+                let user = u.unwrap_or_else(|| self.user.clone());
+                let result = self.authenticator.set_password(&user, &p);
+                self.write_admin_status(result)?;
+            }
+            Command::Users => {
+                let identities = match self.authenticator.list_users() {
+                    Ok(identities) => identities,
+                    Err(e) => {
+                        self.write_admin_status(Err(e))?;
+                        return Ok(true);
+                    }
+                };
+                self.write_status(Status::Okay)?;
+                let list = identities.iter()
+                    .map(|i| format!("{} {}", quote_arg(&i.user), i.permissions))
+                    .collect();
+                ::common::send_list_file(&mut self.transport, list)?;
+            }
+            Command::Hello(version, capabilities) => {
+                let mut supported = Capability::supported();
+                if !self.authenticator.supports_scram() {
+                    supported.retain(|c| *c != Capability::Scram);
+                }
+                let hello = Command::Hello(PROTOCOL_VERSION, supported.clone());
+                if version < MIN_PROTOCOL_VERSION {
+                    self.write_status(Status::UnsupportedVersion)?;
+                    self.write_line(&hello.to_string())?;
+                    return Ok(true);
+                }
+                self.write_status(Status::Okay)?;
+                self.write_line(&hello.to_string())?;
+                let negotiated = Capability::negotiate(&supported, &capabilities);
+                if negotiated.contains(&Capability::Frame) {
+                    self.transport.set_encoding(Encoding::Frame);
+                }
+                self.transport.set_checksum(negotiated.contains(&Capability::Checksum));
+            }
         }
-        self.sender.send(1).unwrap();
-        Ok(())
+        Ok(true)
     }
 
-    /// Receive file from client
-    fn recv_file(&mut self) -> Result<Vec<u8>> {
-        ::common::recv_file(&mut self.stream)
+    /// Open a session in home of user, returning false if user name can't have one
+    fn start_session(&mut self, identity: &Identity) -> Result<bool> {
+        let home = match identity.home {
+            Some(ref home) => home.clone(),
+            None => {
+                let user = &identity.user;
+                if user.contains('/') || user == "." || user == ".." {
+                    return Ok(false);
+                }
+                self.home_template.replace("{user}", user)
+            }
+        };
+        let home = ::common::canonicalize(format!("/{}", home));
+        self.storage.mkdir(&home)?;
+        self.real_home = self.storage.real_path(&home).unwrap_or_else(|_| home.clone());
+        self.home = Some(home);
+        self.cwd = "/".to_string();
+        self.user = identity.user.clone();
+        self.permissions = identity.permissions;
+        Ok(true)
     }
 
-    /// Send file to client
-    fn send_file(&mut self, path: &str) -> Result<()> {
-        ::common::send_file(&mut self.stream, path)
+    /// Check if user is allowed to run command
+    fn is_allowed(&self, command: &Command) -> bool {
+        let permissions = &self.permissions;
+        match *command {
+            Command::Get(_) |
+            Command::GetAt(_, _) |
+            Command::Size(_) |
+            Command::Stat(_) => permissions.read,
+            Command::Put(_) |
+            Command::PutAt(_, _) |
+            Command::Resume(_) => permissions.write,
+            Command::List(_) |
+            Command::ListLong(_) => permissions.list,
+            Command::Mkdir(_) => permissions.mkdir,
+            Command::Rm(_) |
+            Command::Rmdir(_, _) => permissions.delete,
+            Command::Mv(_, _) => permissions.write && permissions.delete,
+            Command::Cp(_, _, _) => permissions.read && permissions.write,
+            Command::UserAdd(_, _, _) |
+            Command::UserDel(_) |
+            Command::Users => permissions.admin,
+            Command::Passwd(Some(ref u), _) => {
+                permissions.admin || (*u == self.user && self.user != "anonymous")
+            }
+            Command::Passwd(None, _) => self.user != "anonymous",
+            _ => true,
+        }
+    }
+
+    /// Send challenge to a client starting a challenge-response login
+    ///
+    /// Unknown users get a challenge built from fake keys, which don't change between
+    /// challenges either, so that they can't be told apart from known ones until the proof
+    /// is checked.
+    fn auth(&mut self, user: String, client_nonce: &str) -> Result<()> {
+        self.scram = None;
+        if client_nonce.is_empty() || !client_nonce.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return self.write_status(Status::WrongLogin);
+        }
+        let (keys, known) = match self.authenticator.scram_keys(&user) {
+            Some(keys) => (keys, true),
+            None => (ScramKeys::fake(&user)?, false),
+        };
+        let nonce = format!("{}{}", client_nonce, scram::nonce()?);
+        let salt = ::common::to_hex(&keys.salt);
+        let auth_message = scram::auth_message(&user, client_nonce, &nonce, &salt, keys.iterations);
+        let challenge = format!("{} {} {}", nonce, salt, keys.iterations);
+        self.scram = Some(ScramState {
+            user,
+            keys,
+            auth_message,
+            known,
+        });
+        self.write_status(Status::Okay)?;
+        self.write_line(&challenge)
+    }
+
+    /// Check proof of a client answering a challenge and open its session
+    fn proof(&mut self, proof: &str) -> Result<()> {
+        let state = match self.scram.take() {
+            Some(state) => state,
+            None => return self.write_status(Status::WrongLogin),
+        };
+        let valid = match ::common::from_hex(proof) {
+            Some(ref proof) => {
+                scram::verify_proof(&state.keys.stored_key, &state.auth_message, proof)
+            }
+            None => false,
+        };
+        let identity = match self.authenticator.identity(&state.user) {
+            Some(ref identity) if state.known && valid && self.start_session(identity)? => {
+                identity.clone()
+            }
+            _ => return self.write_status(Status::WrongLogin),
+        };
+        let signature = scram::server_signature(&state.keys.server_key, &state.auth_message);
+        self.authenticator.upgrade(&identity.user, state.keys);
+        self.write_status(Status::Connected)?;
+        self.write_line(&::common::to_hex(&signature))
+    }
+
+    /// Send file to client, starting at offset
+    fn get(&mut self, p: &str, offset: u64) -> Result<()> {
+        let path = match self.resolve(p, true)? {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let metadata = match self.storage.stat(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => return self.write_status(Status::PathUnknown),
+        };
+        if metadata.kind != FileKind::File {
+            return self.write_status(Status::NotFile);
+        }
+        if offset > metadata.size {
+            return self.write_status(Status::InvalidOffset);
+        }
+        let mut reader = self.storage.open(&path, offset)?;
+        self.write_status(Status::Okay)?;
+        ::common::send_file_from(&mut self.transport, &mut reader, metadata.size - offset)
+    }
+
+    /// Receive file from client, writing it from offset
+    ///
+    /// Data is written into a hidden temporary file next to the destination, which
+    /// replaces the destination only once the whole file has been received. Data received
+    /// by an interrupted upload is kept in a hidden partial file, from which an upload at
+    /// an offset continues.
+    fn put(&mut self, p: &str, offset: u64) -> Result<()> {
+        let path = match self.resolve(p, true)? {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Ok(metadata) = self.storage.stat(&path) {
+            if metadata.kind == FileKind::Dir {
+                return self.write_status(Status::NotFile);
+            }
+        }
+        let tmp_path = upload_path(&path);
+        let partial_path = partial_path(&path);
+        let mut writer = match self.storage.create(&tmp_path) {
+            Ok(writer) => writer,
+            Err(_) => return self.write_status(Status::PathUnknown),
+        };
+        if offset > 0 {
+            let copied = match self.storage.open(&partial_path, 0) {
+                Ok(current) => io::copy(&mut current.take(offset), &mut writer)?,
+                Err(_) => 0,
+            };
+            if copied < offset {
+                drop(writer);
+                let _ = self.storage.remove_file(&tmp_path);
+                return self.write_status(Status::InvalidOffset);
+            }
+        }
+        self.write_status(Status::Okay)?;
+        let received = match ::common::recv_file_to(&mut self.transport, &mut writer) {
+            Ok(_) => self.storage.finish(writer),
+            Err(Error(ErrorKind::ChecksumMismatch, state)) => {
+                drop(writer);
+                Err(Error(ErrorKind::ChecksumMismatch, state))
+            }
+            Err(e) => {
+                // Keep what was received for a later upload at an offset
+                if self.storage.finish(writer).is_err() ||
+                   self.storage.rename(&tmp_path, &partial_path).is_err() {
+                    let _ = self.storage.remove_file(&tmp_path);
+                }
+                return Err(e);
+            }
+        };
+        match received {
+            Ok(_) => {
+                self.storage.rename(&tmp_path, &path)?;
+                let _ = self.storage.remove_file(&partial_path);
+                if self.transport.checksum() {
+                    self.write_status(Status::Okay)?;
+                }
+                Ok(())
+            }
+            Err(Error(ErrorKind::ChecksumMismatch, _)) => {
+                self.storage.remove_file(&tmp_path)?;
+                self.write_status(Status::ChecksumMismatch)
+            }
+            Err(e) => {
+                let _ = self.storage.remove_file(&tmp_path);
+                Err(e)
+            }
+        }
+    }
+
+    /// Write status answering a user administration command
+    fn write_admin_status(&mut self, result: Result<()>) -> Result<()> {
+        let status = match result {
+            Ok(_) => Status::Okay,
+            Err(Error(ErrorKind::UserExists(_), _)) => Status::UserExists,
+            Err(Error(ErrorKind::UserUnknown(_), _)) => Status::UserUnknown,
+            Err(Error(ErrorKind::InvalidUserName(_), _)) => Status::InvalidUserName,
+            Err(Error(ErrorKind::Unsupported, _)) => Status::Unsupported,
+            Err(e) => return Err(e),
+        };
+        self.write_status(status)
     }
 
     /// Send list of file
-    fn send_list_file(&mut self, local_path: &str) -> Result<()> {
-        let list = self.list_files(local_path)?;
-        ::common::send_list_file(&mut self.stream, list)
+    fn send_list_file(&mut self, path: &str) -> Result<()> {
+        let list = self.list_files(path)?;
+        ::common::send_list_file(&mut self.transport, list)
     }
 
     /// Read command sended by client
     fn read_command(&mut self) -> Result<Command> {
-        let mut buf = String::new();
-        ::common::read_line(&mut self.stream, &mut buf)?;
-        Command::try_from(buf)
+        self.transport.read_command()
+    }
+
+    /// Check if server is shutting down
+    fn is_stopping(&self) -> bool {
+        self.watch.as_ref().is_some_and(|watch| watch.is_stopping())
+    }
+
+    /// Tell server whether connection waits for a command
+    fn set_idle(&self, idle: bool) {
+        if let Some(ref watch) = self.watch {
+            watch.set_idle(idle);
+        }
+    }
+
+    /// Tell client that server is leaving
+    fn disconnect(&mut self) -> Result<bool> {
+        self.transport.write_notice(Status::Disconnected)?;
+        Ok(false)
     }
 
     /// Write status to client
     fn write_status(&mut self, status: Status) -> Result<()> {
-        let status = status as u8;
-        self.stream.write(&[status])?;
-        Ok(())
+        self.transport.write_status(status)
+    }
+
+    /// Write a single line to client
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        self.transport.write_line(line)
     }
 
-    /// Return a valid path from server root
-    fn to_server_path(&self, path: &str) -> String {
-        let root = self.root.clone().unwrap();
-        let root_str = ::common::beautify_path(&root.display().to_string());
-        let cwd = ::common::beautify_path(&self.cwd);
-        let mut path_str = if path.starts_with("/") {
-            let path_str = format!("{}/{}", root_str, path);
-            ::common::canonicalize(&path_str)
+    /// Resolve a client path to a storage path confined in user home
+    ///
+    /// Answers `Status::PermissionDenied` and returns `None` if path leaves home, with
+    /// `..` or through a symbolic link. The last component is only followed if follow is
+    /// set.
+    fn resolve(&mut self, path: &str, follow: bool) -> Result<Option<String>> {
+        match self.confine(path, follow) {
+            Ok(path) => Ok(Some(path)),
+            Err(_) => {
+                self.write_status(Status::PermissionDenied)?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Convert a client path to a storage path, failing if it leaves user home
+    fn confine(&self, path: &str, follow: bool) -> Result<String> {
+        let home = self.home.as_ref().unwrap();
+        let full_path = if path.starts_with('/') {
+            path.to_owned()
         } else {
-            let path_str = format!("{}/{}/{}", root_str, cwd, path);
-            ::common::canonicalize(&path_str)
+            format!("{}/{}", self.cwd, path)
         };
-        if path_str.contains(&root_str) {
-            path_str.drain(root_str.len()..).collect()
-        } else {
-            "/".to_string()
+        let mut names = Vec::new();
+        for name in full_path.split('/') {
+            match name {
+                "" | "." => {}
+                ".." => {
+                    if names.pop().is_none() {
+                        bail!(ErrorKind::PermissionDenied);
+                    }
+                }
+                name => names.push(name),
+            }
+        }
+        let path = names.iter().fold(home.clone(), |path, name| format!("{}/{}", path, name));
+        let real_path = match names.pop() {
+            Some(name) if !follow => {
+                let parent = &path[..path.len() - name.len() - 1];
+                format!("{}/{}", self.storage.real_path(parent)?.trim_end_matches('/'), name)
+            }
+            _ => self.storage.real_path(&path)?,
+        };
+        if !is_within(&real_path, &self.real_home) {
+            bail!(ErrorKind::PermissionDenied);
         }
+        Ok(path)
     }
 
-    /// Return a valid from system root
-    fn to_root_path(&self, server_path: &str) -> String {
-        format!("{}/{}", self.root.clone().unwrap().display(), server_path)
+    /// Check that no link nested in a tree to copy leaves user home or loops
+    ///
+    /// ancestors holds real paths of the directories being walked.
+    fn confine_tree(&self, path: &str, ancestors: &mut Vec<String>) -> Result<()> {
+        let real_path = self.storage.real_path(path)?;
+        if !is_within(&real_path, &self.real_home) || ancestors.contains(&real_path) {
+            bail!(ErrorKind::PermissionDenied);
+        }
+        if self.storage.stat(path)?.kind == FileKind::Dir {
+            ancestors.push(real_path);
+            for entry in self.storage.list(path)? {
+                self.confine_tree(&format!("{}/{}", path, entry.name), ancestors)?;
+            }
+            ancestors.pop();
+        }
+        Ok(())
     }
 
-    /// List files from root path
-    fn list_files(&self, server_path: &str) -> Result<Vec<String>> {
-        let root = self.root.clone().unwrap();
-        let root_str = ::common::beautify_path(&format!("{}/{}", root.display(), server_path));
+    /// Return path from user home of a storage path
+    ///
+    /// Home itself is the empty path.
+    fn to_server_path<'a>(&self, path: &'a str) -> &'a str {
+        &path[self.home.as_ref().unwrap().len()..]
+    }
+
+    /// List files from storage path
+    fn list_files(&self, path: &str) -> Result<Vec<String>> {
+        let server_path = ::common::beautify_path(self.to_server_path(path));
         let mut list = Vec::new();
-        let path = root.join(root_str);
-        let path_name = ::common::beautify_path(server_path);
-        let server_path = ::common::beautify_path(server_path);
-        if path.is_dir() {
-            for entry in path.read_dir()? {
-                let entry = entry?;
-                let file_name = entry.file_name();
-                let file_name = file_name.to_str().unwrap();
-                let mut file_str = format!("{}/{}", server_path, file_name);
-                if entry.path().is_dir() {
-                    file_str.push('/');
-                }
-                list.push(file_str);
+        match self.storage.stat(path) {
+            Ok(ref metadata) if metadata.kind == FileKind::Dir => {
+                for entry in self.storage.list(path)? {
+                    if entry.name.starts_with(UPLOAD_PREFIX) {
+                        continue;
+                    }
+                    let mut file_str = format!("{}/{}", server_path, entry.name);
+                    let entry_path = format!("{}/{}", path, entry.name);
+                    if self.storage.stat(&entry_path).is_ok_and(|m| m.kind == FileKind::Dir) {
+                        file_str.push('/');
+                    }
+                    list.push(file_str);
+                }
+            }
+            _ => list.push(server_path),
+        }
+        Ok(list)
+    }
+
+    /// List entries of a directory with their metadata
+    fn list_entries(&self, path: &str) -> Result<Vec<DirEntry>> {
+        let mut list = Vec::new();
+        if self.storage.stat(path)?.kind == FileKind::Dir {
+            for entry in self.storage.list(path)? {
+                if !entry.name.starts_with(UPLOAD_PREFIX) {
+                    list.push(entry);
+                }
             }
         } else {
-            list.push(path_name);
+            let name = path.rsplit('/').next().unwrap_or("").to_string();
+            list.push(DirEntry {
+                name,
+                metadata: self.storage.symlink_stat(path)?,
+            });
         }
+        list.sort_by(|a, b| a.name.cmp(&b.name));
         Ok(list)
     }
 }
+
+/// Build a unique hidden path next to path to receive an upload
+fn upload_path(path: &str) -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let (dir, name) = match path.rfind('/') {
+        Some(i) => (&path[..i], &path[i + 1..]),
+        None => ("", path),
+    };
+    let id = COUNTER.fetch_add(1, Ordering::SeqCst);
+    format!("{}/{}{}.{}.{}", dir, UPLOAD_PREFIX, name, process::id(), id)
+}
+
+/// Get path of the hidden file keeping an interrupted upload to path
+fn partial_path(path: &str) -> String {
+    let (dir, name) = match path.rfind('/') {
+        Some(i) => (&path[..i], &path[i + 1..]),
+        None => ("", path),
+    };
+    format!("{}/{}{}{}", dir, UPLOAD_PREFIX, name, PARTIAL_SUFFIX)
+}
+
+/// Check if path is dir or lies inside it
+fn is_within(path: &str, dir: &str) -> bool {
+    let dir = dir.trim_end_matches('/');
+    dir.is_empty() || path == dir || path.starts_with(&format!("{}/", dir))
+}
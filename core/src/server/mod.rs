@@ -1,17 +1,38 @@
 //! Soft server to handle soft client
+#[cfg(feature = "async")]
+mod async_server;
+pub mod auth;
+mod builder;
 mod connection;
+mod pool;
+mod shutdown;
+pub mod storage;
 pub mod users;
 
-use APP_INFO;
-use app_dirs::{AppDataType, app_dir};
+#[cfg(feature = "async")]
+pub use self::async_server::AsyncSoftServer;
+pub use self::builder::{DEFAULT_HOME, SoftServerBuilder};
+pub use self::pool::ConnectionCounts;
+pub use self::shutdown::ShutdownHandle;
+
 use error::*;
+use self::auth::Authenticator;
 use self::connection::SoftConnection;
+use self::pool::Pool;
+use self::shutdown::Watch;
+use self::storage::{LocalStorage, MemoryStorage, Storage};
 use self::users::Users;
-use std::io::{Read, Write};
-use std::sync::mpsc;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+/// Delay between checks of connections while shutting down or waiting for a free thread
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Delay before accepting again after a failure of listener
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
 
 /// Soft server
 ///
@@ -19,69 +40,244 @@ use std::time::Duration;
 /// use soft_core::server::SoftServer;
 ///
 /// let mut server = SoftServer::new("server", None, true);
-pub struct SoftServer {
-    connection_handlers: Vec<mpsc::Receiver<u8>>,
-    users: Arc<Users>,
-    max_threads: usize,
+pub struct SoftServer<St: Storage = LocalStorage, A: Authenticator = Users> {
+    pool: Pool,
+    authenticator: Arc<A>,
+    storage: Arc<St>,
+    home: String,
     allow_anonymous: bool,
+    shutdown: ShutdownHandle,
 }
 
-impl SoftServer {
+impl SoftServer<LocalStorage> {
     /// Initialize a new server from stream
+    ///
+    /// Files are kept in the user data directory and users in its `db/<name>` directory.
+    /// Use `SoftServerBuilder` to choose them.
     pub fn new(name: &str,
                max_threads: Option<usize>,
                allow_anonymous: bool)
                -> Result<SoftServer> {
+        builder(name, max_threads, allow_anonymous)?.build()
+    }
+}
+
+impl SoftServer<MemoryStorage> {
+    /// Initialize a new server keeping files and users in memory
+    pub fn in_memory(max_threads: Option<usize>, allow_anonymous: bool) -> SoftServer<MemoryStorage> {
+        SoftServer::from_parts(Users::new(),
+                               MemoryStorage::new(),
+                               DEFAULT_HOME.to_string(),
+                               max_threads,
+                               allow_anonymous)
+    }
+}
+
+impl<St: Storage> SoftServer<St> {
+    /// Initialize a new server keeping files in storage
+    pub fn with_storage(name: &str,
+                        storage: St,
+                        max_threads: Option<usize>,
+                        allow_anonymous: bool)
+                        -> Result<SoftServer<St>> {
+        builder(name, max_threads, allow_anonymous)?.build_with(storage)
+    }
+
+    /// Get all users
+    pub fn get_users(&self) -> Arc<Users> {
+        self.authenticator.clone()
+    }
+}
+
+impl<St: Storage, A: Authenticator> SoftServer<St, A> {
+    /// Initialize a new server from its parts
+    fn from_parts(authenticator: A,
+                  storage: St,
+                  home: String,
+                  max_threads: Option<usize>,
+                  allow_anonymous: bool)
+                  -> SoftServer<St, A> {
         let mut max_threads = max_threads.unwrap_or(8);
         if max_threads < 1 {
             max_threads = 1;
         }
-        let path = app_dir(AppDataType::UserData,
-                           &APP_INFO,
-                           format!("db/{}", name).as_str())?;
-        Ok(SoftServer {
-            connection_handlers: Vec::new(),
-            users: Arc::new(Users::load(path)?),
-            max_threads: max_threads,
-            allow_anonymous: allow_anonymous,
-        })
+        SoftServer {
+            pool: Pool::new(max_threads),
+            authenticator: Arc::new(authenticator),
+            storage: Arc::new(storage),
+            home,
+            allow_anonymous,
+            shutdown: ShutdownHandle::default(),
+        }
     }
 
     /// Add a new connection to server
+    ///
+    /// Connection is run by a thread of the pool, waiting for one to be free if
+    /// `max_threads` connections are already running. A connection ending with an error or
+    /// a panic only frees its thread, and is counted as failed.
     pub fn new_connection<S: Read + Write + Send + 'static>(&mut self, stream: S) {
-        while self.connection_handlers.len() + 1 > self.max_threads {
-            self.check_connections();
-            thread::sleep(Duration::from_secs(5));
+        let watch = Arc::new(Watch::new(self.shutdown.clone()));
+        self.run_connection(stream, watch);
+    }
+
+    /// Accept clients of listener until shutdown is requested
+    ///
+    /// Errors of accept don't stop the server. Once shutdown is requested with a
+    /// `ShutdownHandle`, listener is closed, idle clients and clients ending their command
+    /// are told they are disconnected, and clients still running a command when grace ends
+    /// are cut. Returns when all clients accepted here are gone.
+    pub fn serve(&mut self, listener: TcpListener) -> Result<()> {
+        self.serve_with(listener, Ok)
+    }
+
+    /// Accept clients of listener like `serve`, wrapping their stream, for example with
+    /// `tls::accept`
+    ///
+    /// A client whose stream can't be wrapped is dropped.
+    pub fn serve_with<S, F>(&mut self, listener: TcpListener, mut wrap: F) -> Result<()>
+        where S: Read + Write + Send + 'static,
+              F: FnMut(TcpStream) -> Result<S>
+    {
+        self.shutdown.listen(Some(listener.local_addr()?));
+        let mut clients = Vec::new();
+        while !self.shutdown.is_requested() {
+            if self.pool.counts().active >= self.pool.size() {
+                self.pool.wait(POLL_INTERVAL);
+                continue;
+            }
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    if !is_transient(&e) {
+                        thread::sleep(ACCEPT_BACKOFF);
+                    }
+                    continue;
+                }
+            };
+            if self.shutdown.is_requested() {
+                break;
+            }
+            let socket = match stream.try_clone() {
+                Ok(socket) => socket,
+                Err(_) => continue,
+            };
+            let stream = match wrap(stream) {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            let watch = Arc::new(Watch::new(self.shutdown.clone()));
+            clients.retain(is_running);
+            clients.push((socket, watch.clone()));
+            self.run_connection(stream, watch);
         }
-        let (tx, rx) = mpsc::channel();
-        let users = self.users.clone();
-        let allow_anonymous = self.allow_anonymous.clone();
-        thread::spawn(move || {
-            let mut connection = SoftConnection::new(stream, tx, users, allow_anonymous);
-            // TODO error handling
-            connection.run().unwrap();
-        });
-        self.connection_handlers.push(rx);
+        self.shutdown.listen(None);
+        drop(listener);
+        self.drain(clients);
+        Ok(())
     }
 
-    /// Get all users
-    pub fn get_users(&self) -> Arc<Users> {
-        self.users.clone()
+    /// Get a handle stopping `serve`
+    ///
+    /// Shutdown is final: connections started afterwards are disconnected right away.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
     }
 
-    /// Check connections and remove those who are stopped
-    fn check_connections(&mut self) {
-        self.connection_handlers.retain(|c| {
-            let recv = c.try_recv();
-            recv.is_err()
-        });
+    /// Run a connection watching shutdown of server
+    fn run_connection<S: Read + Write + Send + 'static>(&mut self, stream: S, watch: Arc<Watch>) {
+        let authenticator = self.authenticator.clone();
+        let storage = self.storage.clone();
+        let home = self.home.clone();
+        let allow_anonymous = self.allow_anonymous;
+        self.pool.execute(Box::new(move || {
+            let mut connection = SoftConnection::new(stream,
+                                                     authenticator,
+                                                     storage,
+                                                     home,
+                                                     allow_anonymous,
+                                                     Some(watch));
+            connection.run().is_ok()
+        }));
     }
-}
 
-impl Drop for SoftServer {
-    fn drop(&mut self) {
-        for connection in &self.connection_handlers {
-            let _ = connection.recv();
+    /// Wait for clients to end after shutdown, closing input of idle ones
+    fn drain(&self, mut clients: Vec<(TcpStream, Arc<Watch>)>) {
+        let deadline = Instant::now() + self.shutdown.grace().unwrap_or_default();
+        loop {
+            clients.retain(is_running);
+            if clients.is_empty() {
+                return;
+            }
+            let how = if Instant::now() < deadline {
+                Shutdown::Read
+            } else {
+                Shutdown::Both
+            };
+            for (socket, watch) in &clients {
+                if how == Shutdown::Both || watch.is_idle() {
+                    let _ = socket.shutdown(how);
+                }
+            }
+            self.pool.wait(POLL_INTERVAL);
         }
     }
+
+    /// Get counts of running and ended connections
+    pub fn connection_counts(&self) -> ConnectionCounts {
+        self.pool.counts()
+    }
+
+    /// Get number of running connections
+    pub fn active_connections(&self) -> usize {
+        self.pool.counts().active
+    }
+
+    /// Get maximum number of connections running at once
+    pub fn max_threads(&self) -> usize {
+        self.pool.size()
+    }
+
+    /// Turn into a server handling connections as tokio tasks, with same configuration
+    ///
+    /// Connections aren't limited by `max_threads` anymore, idle ones don't hold threads.
+    #[cfg(feature = "async")]
+    pub fn into_async(self) -> AsyncSoftServer<St, A> {
+        AsyncSoftServer::from_parts(self.authenticator,
+                                    self.storage,
+                                    self.home,
+                                    self.allow_anonymous)
+    }
+
+    /// Get authenticator checking users
+    pub fn get_authenticator(&self) -> Arc<A> {
+        self.authenticator.clone()
+    }
+
+    /// Get storage keeping files
+    pub fn get_storage(&self) -> Arc<St> {
+        self.storage.clone()
+    }
+}
+
+/// Check if a client accepted by `serve` is still running, its connection holding the watch
+fn is_running(client: &(TcpStream, Arc<Watch>)) -> bool {
+    Arc::strong_count(&client.1) > 1
+}
+
+/// Check if an error of accept only concerns the client being accepted
+fn is_transient(error: &io::Error) -> bool {
+    matches!(error.kind(),
+             io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset |
+             io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock |
+             io::ErrorKind::TimedOut)
+}
+
+/// Create builder of a named server
+fn builder(name: &str, max_threads: Option<usize>, allow_anonymous: bool) -> Result<SoftServerBuilder> {
+    let mut builder = SoftServerBuilder::named(name)?.allow_anonymous(allow_anonymous);
+    if let Some(max_threads) = max_threads {
+        builder = builder.max_threads(max_threads);
+    }
+    Ok(builder)
 }
@@ -0,0 +1,110 @@
+//! Pool of threads running connections of a server
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, mpsc};
+use std::thread;
+use std::time::Duration;
+
+/// Connection handed to a worker, returning false if it failed
+type Job = Box<dyn FnOnce() -> bool + Send>;
+
+/// Counts of connections handled by a server
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ConnectionCounts {
+    /// Connections currently running
+    pub active: usize,
+    /// Connections which ended, whether they failed or not
+    pub finished: u64,
+    /// Connections which ended with an error or a panic
+    pub failed: u64,
+}
+
+/// Counts shared with workers, signaled on every change
+struct Shared {
+    counts: Mutex<ConnectionCounts>,
+    changed: Condvar,
+}
+
+/// Fixed number of workers taking connections from a queue
+pub struct Pool {
+    sender: Option<mpsc::Sender<Job>>,
+    workers: Vec<thread::JoinHandle<()>>,
+    shared: Arc<Shared>,
+}
+
+impl Pool {
+    /// Start size workers
+    pub fn new(size: usize) -> Pool {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let shared = Arc::new(Shared {
+            counts: Mutex::new(ConnectionCounts::default()),
+            changed: Condvar::new(),
+        });
+        let workers = (0..size)
+            .map(|_| {
+                let receiver = receiver.clone();
+                let shared = shared.clone();
+                thread::spawn(move || work(&receiver, &shared))
+            })
+            .collect();
+        Pool {
+            sender: Some(sender),
+            workers,
+            shared,
+        }
+    }
+
+    /// Get number of workers
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Get counts of connections
+    pub fn counts(&self) -> ConnectionCounts {
+        *self.shared.counts.lock().unwrap()
+    }
+
+    /// Wait for counts to change for at most timeout, and get them
+    pub fn wait(&self, timeout: Duration) -> ConnectionCounts {
+        let counts = self.shared.counts.lock().unwrap();
+        *self.shared.changed.wait_timeout(counts, timeout).unwrap().0
+    }
+
+    /// Run job once a worker is free, waiting for one if all are busy
+    pub fn execute(&self, job: Job) {
+        let mut counts = self.shared.counts.lock().unwrap();
+        while counts.active >= self.workers.len() {
+            counts = self.shared.changed.wait(counts).unwrap();
+        }
+        counts.active += 1;
+        drop(counts);
+        if let Some(ref sender) = self.sender {
+            sender.send(job).unwrap();
+        }
+    }
+}
+
+impl Drop for Pool {
+    /// Let running connections end on their own, workers stop after them
+    fn drop(&mut self) {
+        self.sender = None;
+    }
+}
+
+/// Run jobs of queue until pool is dropped and queue is empty
+fn work(receiver: &Mutex<mpsc::Receiver<Job>>, shared: &Shared) {
+    loop {
+        let job = match receiver.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        let succeeded = panic::catch_unwind(AssertUnwindSafe(job)).unwrap_or(false);
+        let mut counts = shared.counts.lock().unwrap();
+        counts.active -= 1;
+        counts.finished += 1;
+        if !succeeded {
+            counts.failed += 1;
+        }
+        shared.changed.notify_all();
+    }
+}
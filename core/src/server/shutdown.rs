@@ -0,0 +1,111 @@
+//! Stopping a server accepting clients with `SoftServer::serve`
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// State shared by the handles of a server
+#[derive(Default)]
+struct Shared {
+    /// Time left to running commands once shutdown is requested
+    grace: Mutex<Option<Duration>>,
+    /// Address of the listener of a running `serve`
+    address: Mutex<Option<SocketAddr>>,
+}
+
+/// Handle stopping a server running `SoftServer::serve`, from any thread
+///
+/// ```no_run
+/// use soft_core::server::SoftServer;
+/// use std::net::TcpListener;
+/// use std::thread;
+/// use std::time::Duration;
+///
+/// let mut server = SoftServer::new("server", None, true).unwrap();
+/// let shutdown = server.shutdown_handle();
+/// let listener = TcpListener::bind("127.0.0.1:9045").unwrap();
+/// let serving = thread::spawn(move || server.serve(listener));
+/// shutdown.shutdown(Duration::from_secs(10));
+/// serving.join().unwrap().unwrap();
+/// ```
+#[derive(Clone, Default)]
+pub struct ShutdownHandle {
+    shared: Arc<Shared>,
+}
+
+impl ShutdownHandle {
+    /// Stop accepting clients, leaving at most grace to running commands
+    ///
+    /// Only the first request sets the deadline.
+    pub fn shutdown(&self, grace: Duration) {
+        {
+            let mut current = self.shared.grace.lock().unwrap();
+            if current.is_none() {
+                *current = Some(grace);
+            }
+        }
+        // Wake the listener blocked on accept
+        if let Some(address) = *self.shared.address.lock().unwrap() {
+            let _ = TcpStream::connect(reachable(address));
+        }
+    }
+
+    /// Check if shutdown was requested
+    pub fn is_requested(&self) -> bool {
+        self.grace().is_some()
+    }
+
+    /// Get time left to running commands, if shutdown was requested
+    pub fn grace(&self) -> Option<Duration> {
+        *self.shared.grace.lock().unwrap()
+    }
+
+    /// Set or clear address of the listener to wake on shutdown
+    pub(super) fn listen(&self, address: Option<SocketAddr>) {
+        *self.shared.address.lock().unwrap() = address;
+    }
+}
+
+/// Shutdown state seen by a connection
+pub struct Watch {
+    shutdown: ShutdownHandle,
+    idle: AtomicBool,
+}
+
+impl Watch {
+    /// Watch shutdown of a server
+    pub fn new(shutdown: ShutdownHandle) -> Watch {
+        Watch {
+            shutdown,
+            idle: AtomicBool::new(false),
+        }
+    }
+
+    /// Check if connection must end
+    pub fn is_stopping(&self) -> bool {
+        self.shutdown.is_requested()
+    }
+
+    /// Check if connection is waiting for a command
+    pub fn is_idle(&self) -> bool {
+        self.idle.load(Ordering::SeqCst)
+    }
+
+    /// Mark connection as waiting for a command or not
+    pub fn set_idle(&self, idle: bool) {
+        self.idle.store(idle, Ordering::SeqCst);
+    }
+}
+
+/// Get an address connecting to a listener bound to address
+fn reachable(address: SocketAddr) -> SocketAddr {
+    match address.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => {
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), address.port())
+        }
+        IpAddr::V6(ip) if ip.is_unspecified() => {
+            SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), address.port())
+        }
+        _ => address,
+    }
+}
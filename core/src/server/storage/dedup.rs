@@ -0,0 +1,373 @@
+//! Storage deduplicating file contents on local disk
+use error::*;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use super::{Storage, UPLOAD_PREFIX, file_metadata, is_not_found, remove_stale_uploads};
+use types::*;
+
+/// Digest of empty content, held by files being written until they are finished
+const EMPTY_DIGEST: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+/// Storage keeping each distinct file content once
+///
+/// File contents are stored in `blobs/` under their SHA-256 digest, with the number of
+/// files referencing them. The tree of files lives in `tree/`, where each file only holds
+/// the digest and size of its content. A blob is removed when its last reference is.
+pub struct DedupStorage {
+    root: PathBuf,
+    lock: Mutex<()>,
+}
+
+/// Result of a garbage collection pass
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GcStats {
+    /// Number of blobs still referenced
+    pub kept: usize,
+    /// Number of blobs removed
+    pub removed: usize,
+    /// Number of bytes freed by removed blobs
+    pub freed: u64,
+}
+
+/// Writer storing a file content in a temporary blob until it's finished
+pub struct DedupWriter {
+    path: String,
+    tmp_path: PathBuf,
+    file: File,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl Write for DedupWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for DedupWriter {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.tmp_path);
+    }
+}
+
+impl DedupStorage {
+    /// Use root directory as storage, creating it if needed
+    ///
+    /// Uploads left behind by an interrupted server are removed. The references they
+    /// held are only released by the next garbage collection.
+    pub fn new<P: AsRef<Path>>(root: P) -> Result<DedupStorage> {
+        let storage = DedupStorage {
+            root: root.as_ref().to_path_buf(),
+            lock: Mutex::new(()),
+        };
+        for dir in &["tree", "blobs", "tmp"] {
+            let dir = storage.root.join(dir);
+            fs::create_dir_all(&dir)?;
+            remove_stale_uploads(&dir);
+        }
+        Ok(storage)
+    }
+
+    /// Get root directory of storage
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Remove blobs which are no longer referenced and fix reference counts
+    pub fn gc(&self) -> Result<GcStats> {
+        let _lock = self.lock.lock().unwrap();
+        let mut refs = HashMap::new();
+        count_refs(&self.root.join("tree"), &mut refs)?;
+        let mut stats = GcStats::default();
+        for dir in self.root.join("blobs").read_dir()? {
+            let dir = dir?;
+            for entry in dir.path().read_dir()? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if name.ends_with(".refs") {
+                    if !entry.path().with_extension("").exists() {
+                        let _ = fs::remove_file(entry.path());
+                    }
+                    continue;
+                }
+                if name.len() != 64 {
+                    fs::remove_file(entry.path())?;
+                    continue;
+                }
+                match refs.get(&name) {
+                    Some(count) => {
+                        self.write_refs(&name, *count)?;
+                        stats.kept += 1;
+                    }
+                    None => {
+                        stats.freed += entry.metadata()?.len();
+                        stats.removed += 1;
+                        self.write_refs(&name, 0)?;
+                    }
+                }
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Convert a storage path to a path in the tree
+    fn tree_path(&self, path: &str) -> PathBuf {
+        self.root.join("tree").join(path.trim_start_matches('/'))
+    }
+
+    /// Get path of the blob holding content with digest
+    fn blob_path(&self, digest: &str) -> PathBuf {
+        self.root.join("blobs").join(&digest[..2]).join(digest)
+    }
+
+    /// Get path of the reference count of blob with digest
+    fn refs_path(&self, digest: &str) -> PathBuf {
+        self.blob_path(digest).with_extension("refs")
+    }
+
+    /// Read number of references to blob with digest
+    fn read_refs(&self, digest: &str) -> Result<u64> {
+        match fs::read_to_string(self.refs_path(digest)) {
+            Ok(count) => Ok(count.trim().parse()?),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write number of references to blob with digest, removing it if there is none
+    fn write_refs(&self, digest: &str, count: u64) -> Result<()> {
+        if count == 0 {
+            let _ = fs::remove_file(self.blob_path(digest));
+            let _ = fs::remove_file(self.refs_path(digest));
+            return Ok(());
+        }
+        let refs_path = self.refs_path(digest);
+        let tmp_path = refs_path.with_extension("refs.tmp");
+        fs::write(&tmp_path, format!("{}\n", count))?;
+        fs::rename(&tmp_path, &refs_path)?;
+        Ok(())
+    }
+
+    /// Add a reference to blob with digest
+    fn acquire(&self, digest: &str) -> Result<()> {
+        let count = self.read_refs(digest)?;
+        self.write_refs(digest, count + 1)
+    }
+
+    /// Remove a reference to blob with digest
+    fn release(&self, digest: &str) -> Result<()> {
+        let count = self.read_refs(digest)?;
+        self.write_refs(digest, count.saturating_sub(1))
+    }
+
+    /// Write a file in the tree pointing to a blob, releasing the one it replaces
+    fn write_pointer(&self, tree_path: &Path, digest: &str, size: u64) -> Result<()> {
+        self.acquire(digest)?;
+        let old = read_pointer(tree_path).ok();
+        if let Err(e) = fs::write(tree_path, format!("{} {}\n", digest, size)) {
+            let _ = self.release(digest);
+            return Err(e.into());
+        }
+        if let Some((old_digest, _)) = old {
+            self.release(&old_digest)?;
+        }
+        Ok(())
+    }
+
+    /// Release all blobs referenced by files in a tree directory
+    fn release_all(&self, dir: &Path) -> Result<()> {
+        for entry in dir.read_dir()? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                self.release_all(&entry.path())?;
+            } else if let Ok((digest, _)) = read_pointer(&entry.path()) {
+                self.release(&digest)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy a file or a directory of the tree, adding references to its blobs
+    fn copy_tree(&self, src: &Path, dst: &Path) -> Result<()> {
+        if src.is_dir() {
+            fs::create_dir_all(dst)?;
+            for entry in src.read_dir()? {
+                let entry = entry?;
+                self.copy_tree(&entry.path(), &dst.join(entry.file_name()))?;
+            }
+            Ok(())
+        } else {
+            let (digest, size) = read_pointer(src)?;
+            self.write_pointer(dst, &digest, size)
+        }
+    }
+}
+
+impl Storage for DedupStorage {
+    type Reader = File;
+    type Writer = DedupWriter;
+
+    fn open(&self, path: &str, offset: u64) -> Result<File> {
+        let (digest, _) = read_pointer(&self.tree_path(path))?;
+        let mut file = File::open(self.blob_path(&digest))?;
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(file)
+    }
+
+    fn create(&self, path: &str) -> Result<DedupWriter> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let tree_path = self.tree_path(path);
+        if tree_path.is_dir() {
+            bail!(io::Error::other(format!("{}: is a directory", path)));
+        }
+        if !tree_path.parent().is_some_and(|p| p.is_dir()) {
+            return Err(super::not_found(path));
+        }
+        {
+            // File exists, empty, as soon as it's created
+            let _lock = self.lock.lock().unwrap();
+            let blob_path = self.blob_path(EMPTY_DIGEST);
+            if !blob_path.exists() {
+                fs::create_dir_all(blob_path.parent().unwrap())?;
+                File::create(&blob_path)?;
+            }
+            self.write_pointer(&tree_path, EMPTY_DIGEST, 0)?;
+        }
+        let id = COUNTER.fetch_add(1, Ordering::SeqCst);
+        let tmp_name = format!("{}blob.{}.{}", UPLOAD_PREFIX, process::id(), id);
+        let tmp_path = self.root.join("tmp").join(tmp_name);
+        let file = File::create(&tmp_path)?;
+        Ok(DedupWriter {
+            path: path.to_owned(),
+            tmp_path,
+            file,
+            hasher: Sha256::new(),
+            size: 0,
+        })
+    }
+
+    fn finish(&self, writer: DedupWriter) -> Result<()> {
+        writer.file.sync_all()?;
+        let digest = ::common::to_hex(writer.hasher.clone().finalize().as_slice());
+        let _lock = self.lock.lock().unwrap();
+        let blob_path = self.blob_path(&digest);
+        if !blob_path.exists() {
+            fs::create_dir_all(blob_path.parent().unwrap())?;
+            fs::rename(&writer.tmp_path, &blob_path)?;
+        }
+        self.write_pointer(&self.tree_path(&writer.path), &digest, writer.size)
+    }
+
+    fn list(&self, path: &str) -> Result<Vec<DirEntry>> {
+        let mut list = Vec::new();
+        for entry in self.tree_path(path).read_dir()? {
+            let entry = entry?;
+            let metadata = match tree_metadata(&entry.path()) {
+                Ok(metadata) => metadata,
+                // Entry removed while listing
+                Err(ref e) if is_not_found(e) => continue,
+                Err(e) => return Err(e),
+            };
+            list.push(DirEntry {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                metadata,
+            });
+        }
+        Ok(list)
+    }
+
+    fn stat(&self, path: &str) -> Result<Metadata> {
+        tree_metadata(&self.tree_path(path))
+    }
+
+    fn mkdir(&self, path: &str) -> Result<()> {
+        fs::create_dir_all(self.tree_path(path))?;
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &str) -> Result<()> {
+        let _lock = self.lock.lock().unwrap();
+        let tree_path = self.tree_path(path);
+        let (digest, _) = read_pointer(&tree_path)?;
+        fs::remove_file(&tree_path)?;
+        self.release(&digest)
+    }
+
+    fn remove_dir(&self, path: &str, recursive: bool) -> Result<()> {
+        let _lock = self.lock.lock().unwrap();
+        let tree_path = self.tree_path(path);
+        if recursive {
+            self.release_all(&tree_path)?;
+            fs::remove_dir_all(&tree_path)?;
+        } else {
+            fs::remove_dir(&tree_path)?;
+        }
+        Ok(())
+    }
+
+    fn rename(&self, src: &str, dst: &str) -> Result<()> {
+        let _lock = self.lock.lock().unwrap();
+        let dst_path = self.tree_path(dst);
+        let old = if dst_path.is_file() {
+            read_pointer(&dst_path).ok()
+        } else {
+            None
+        };
+        fs::rename(self.tree_path(src), &dst_path)?;
+        if let Some((old_digest, _)) = old {
+            self.release(&old_digest)?;
+        }
+        Ok(())
+    }
+
+    fn copy(&self, src: &str, dst: &str) -> Result<()> {
+        let _lock = self.lock.lock().unwrap();
+        self.copy_tree(&self.tree_path(src), &self.tree_path(dst))
+    }
+}
+
+/// Read metadata of a file in the tree, with the size of its content
+fn tree_metadata(path: &Path) -> Result<Metadata> {
+    let mut metadata = file_metadata(path, false)?;
+    if metadata.kind == FileKind::File {
+        metadata.size = read_pointer(path)?.1;
+    }
+    Ok(metadata)
+}
+
+/// Read digest and size of the content of a file in the tree
+fn read_pointer(path: &Path) -> Result<(String, u64)> {
+    let mut buf = String::new();
+    File::open(path)?.read_to_string(&mut buf)?;
+    let mut words = buf.split_whitespace();
+    match (words.next(), words.next()) {
+        (Some(digest), Some(size)) if digest.len() == 64 => Ok((digest.to_owned(), size.parse()?)),
+        _ => bail!(io::Error::other(format!("{}: invalid file pointer", path.display()))),
+    }
+}
+
+/// Count references to each blob from files in a tree directory
+fn count_refs(dir: &Path, refs: &mut HashMap<String, u64>) -> Result<()> {
+    for entry in dir.read_dir()? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            count_refs(&entry.path(), refs)?;
+        } else if let Ok((digest, _)) = read_pointer(&entry.path()) {
+            *refs.entry(digest).or_insert(0) += 1;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,134 @@
+//! Storage keeping files on local disk
+use error::*;
+use std::fs::{self, File};
+use std::io::{self, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use super::{Storage, file_metadata, is_not_found, remove_stale_uploads};
+use types::*;
+
+/// Storage keeping files in a directory of the local filesystem
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    /// Use root directory as storage, creating it if needed
+    ///
+    /// Uploads left behind by an interrupted server are removed.
+    pub fn new<P: AsRef<Path>>(root: P) -> Result<LocalStorage> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(&root)?;
+        remove_stale_uploads(&root);
+        Ok(LocalStorage { root })
+    }
+
+    /// Get root directory of storage
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Convert a storage path to a local path
+    fn to_local_path(&self, path: &str) -> PathBuf {
+        self.root.join(path.trim_start_matches('/'))
+    }
+}
+
+impl Storage for LocalStorage {
+    type Reader = File;
+    type Writer = File;
+
+    fn open(&self, path: &str, offset: u64) -> Result<File> {
+        let mut file = File::open(self.to_local_path(path))?;
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(file)
+    }
+
+    fn create(&self, path: &str) -> Result<File> {
+        Ok(File::create(self.to_local_path(path))?)
+    }
+
+    fn finish(&self, writer: File) -> Result<()> {
+        writer.sync_all()?;
+        Ok(())
+    }
+
+    fn list(&self, path: &str) -> Result<Vec<DirEntry>> {
+        let mut list = Vec::new();
+        for entry in self.to_local_path(path).read_dir()? {
+            let entry = entry?;
+            let metadata = match file_metadata(&entry.path(), false) {
+                Ok(metadata) => metadata,
+                // Entry removed while listing
+                Err(ref e) if is_not_found(e) => continue,
+                Err(e) => return Err(e),
+            };
+            list.push(DirEntry {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                metadata,
+            });
+        }
+        Ok(list)
+    }
+
+    fn stat(&self, path: &str) -> Result<Metadata> {
+        file_metadata(&self.to_local_path(path), true)
+    }
+
+    fn symlink_stat(&self, path: &str) -> Result<Metadata> {
+        file_metadata(&self.to_local_path(path), false)
+    }
+
+    fn mkdir(&self, path: &str) -> Result<()> {
+        fs::create_dir_all(self.to_local_path(path))?;
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &str) -> Result<()> {
+        fs::remove_file(self.to_local_path(path))?;
+        Ok(())
+    }
+
+    fn remove_dir(&self, path: &str, recursive: bool) -> Result<()> {
+        if recursive {
+            fs::remove_dir_all(self.to_local_path(path))?;
+        } else {
+            fs::remove_dir(self.to_local_path(path))?;
+        }
+        Ok(())
+    }
+
+    fn rename(&self, src: &str, dst: &str) -> Result<()> {
+        fs::rename(self.to_local_path(src), self.to_local_path(dst))?;
+        Ok(())
+    }
+
+    fn real_path(&self, path: &str) -> Result<String> {
+        let root = fs::canonicalize(&self.root)?;
+        let mut local_path = self.to_local_path(path);
+        let mut missing = Vec::new();
+        let mut real_path = loop {
+            match fs::canonicalize(&local_path) {
+                Ok(real_path) => break real_path,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                    if fs::symlink_metadata(&local_path).is_ok() {
+                        // Dangling link, its target can't be checked
+                        bail!(ErrorKind::PermissionDenied);
+                    }
+                    match local_path.file_name() {
+                        Some(name) => missing.push(name.to_owned()),
+                        None => bail!(ErrorKind::PermissionDenied),
+                    }
+                    local_path.pop();
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+        while let Some(name) = missing.pop() {
+            real_path.push(name);
+        }
+        match real_path.strip_prefix(&root) {
+            Ok(path) => Ok(format!("/{}", path.display())),
+            Err(_) => bail!(ErrorKind::PermissionDenied),
+        }
+    }
+}
@@ -0,0 +1,286 @@
+//! Storage keeping files in memory
+use error::*;
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use super::{Storage, not_found};
+use types::*;
+
+/// Storage keeping files in memory, lost when the last handle is dropped
+///
+/// Cloning a memory storage gives another handle to the same files.
+#[derive(Clone, Default)]
+pub struct MemoryStorage {
+    root: Arc<RwLock<Node>>,
+}
+
+/// Node of the file tree
+struct Node {
+    content: Content,
+    modified: u64,
+}
+
+/// Content of a node
+enum Content {
+    Dir(BTreeMap<String, Node>),
+    File(Arc<Vec<u8>>),
+}
+
+impl Default for Node {
+    fn default() -> Node {
+        Node::dir()
+    }
+}
+
+impl Node {
+    /// Create an empty directory
+    fn dir() -> Node {
+        Node {
+            content: Content::Dir(BTreeMap::new()),
+            modified: now(),
+        }
+    }
+
+    /// Create a file holding data
+    fn file(data: Vec<u8>) -> Node {
+        Node {
+            content: Content::File(Arc::new(data)),
+            modified: now(),
+        }
+    }
+
+    /// Get metadata of node
+    fn metadata(&self) -> Metadata {
+        let (kind, size, permissions) = match self.content {
+            Content::Dir(_) => (FileKind::Dir, 0, 0o755),
+            Content::File(ref data) => (FileKind::File, data.len() as u64, 0o644),
+        };
+        Metadata {
+            kind,
+            size,
+            modified: self.modified,
+            permissions,
+            symlink_target: None,
+        }
+    }
+
+    /// Find node at path
+    fn get(&self, path: &str) -> Option<&Node> {
+        let mut node = self;
+        for name in components(path) {
+            node = match node.content {
+                Content::Dir(ref children) => children.get(name)?,
+                Content::File(_) => return None,
+            };
+        }
+        Some(node)
+    }
+
+    /// Find children of the directory containing path, with the name of path
+    fn parent_mut<'a>(&mut self, path: &'a str) -> Result<(&mut BTreeMap<String, Node>, &'a str)> {
+        let mut names = components(path);
+        let name = match names.pop() {
+            Some(name) => name,
+            None => return Err(other_error("/: is the root directory")),
+        };
+        let mut node = self;
+        for parent in names {
+            node = match node.content {
+                Content::Dir(ref mut children) => {
+                    children.get_mut(parent).ok_or_else(|| not_found(path))?
+                }
+                Content::File(_) => return Err(not_found(path)),
+            };
+        }
+        match node.content {
+            Content::Dir(ref mut children) => Ok((children, name)),
+            Content::File(_) => Err(not_found(path)),
+        }
+    }
+}
+
+impl MemoryStorage {
+    /// Create an empty storage
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+}
+
+/// Reader over the content of a file in memory
+pub struct MemoryReader {
+    data: Arc<Vec<u8>>,
+    pos: usize,
+}
+
+impl Read for MemoryReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = (&self.data[self.pos..]).read(buf)?;
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Writer buffering the content of a file until it's finished
+pub struct MemoryWriter {
+    path: String,
+    data: Vec<u8>,
+}
+
+impl Write for MemoryWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Storage for MemoryStorage {
+    type Reader = MemoryReader;
+    type Writer = MemoryWriter;
+
+    fn open(&self, path: &str, offset: u64) -> Result<MemoryReader> {
+        let root = self.root.read().unwrap();
+        match root.get(path) {
+            Some(&Node { content: Content::File(ref data), .. }) => {
+                Ok(MemoryReader {
+                    data: data.clone(),
+                    pos: (offset as usize).min(data.len()),
+                })
+            }
+            Some(_) => Err(other_error(&format!("{}: is a directory", path))),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn create(&self, path: &str) -> Result<MemoryWriter> {
+        let mut root = self.root.write().unwrap();
+        let (children, name) = root.parent_mut(path)?;
+        if let Some(&Node { content: Content::Dir(_), .. }) = children.get(name) {
+            return Err(other_error(&format!("{}: is a directory", path)));
+        }
+        children.insert(name.to_owned(), Node::file(Vec::new()));
+        Ok(MemoryWriter {
+            path: path.to_owned(),
+            data: Vec::new(),
+        })
+    }
+
+    fn finish(&self, writer: MemoryWriter) -> Result<()> {
+        let mut root = self.root.write().unwrap();
+        let (children, name) = root.parent_mut(&writer.path)?;
+        children.insert(name.to_owned(), Node::file(writer.data));
+        Ok(())
+    }
+
+    fn list(&self, path: &str) -> Result<Vec<DirEntry>> {
+        let root = self.root.read().unwrap();
+        match root.get(path) {
+            Some(&Node { content: Content::Dir(ref children), .. }) => {
+                Ok(children.iter()
+                    .map(|(name, node)| {
+                        DirEntry {
+                            name: name.clone(),
+                            metadata: node.metadata(),
+                        }
+                    })
+                    .collect())
+            }
+            Some(_) => Err(other_error(&format!("{}: not a directory", path))),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn stat(&self, path: &str) -> Result<Metadata> {
+        let root = self.root.read().unwrap();
+        root.get(path).map(|node| node.metadata()).ok_or_else(|| not_found(path))
+    }
+
+    fn mkdir(&self, path: &str) -> Result<()> {
+        let mut root = self.root.write().unwrap();
+        let mut node = &mut *root;
+        for name in components(path) {
+            node = match node.content {
+                Content::Dir(ref mut children) => children.entry(name.to_owned()).or_default(),
+                Content::File(_) => return Err(other_error(&format!("{}: not a directory", path))),
+            };
+        }
+        match node.content {
+            Content::Dir(_) => Ok(()),
+            Content::File(_) => Err(other_error(&format!("{}: file exists", path))),
+        }
+    }
+
+    fn remove_file(&self, path: &str) -> Result<()> {
+        let mut root = self.root.write().unwrap();
+        let (children, name) = root.parent_mut(path)?;
+        match children.get(name) {
+            Some(&Node { content: Content::File(_), .. }) => {}
+            Some(_) => return Err(other_error(&format!("{}: is a directory", path))),
+            None => return Err(not_found(path)),
+        }
+        children.remove(name);
+        Ok(())
+    }
+
+    fn remove_dir(&self, path: &str, recursive: bool) -> Result<()> {
+        let mut root = self.root.write().unwrap();
+        let (children, name) = root.parent_mut(path)?;
+        match children.get(name) {
+            Some(&Node { content: Content::Dir(ref content), .. }) => {
+                if !recursive && !content.is_empty() {
+                    return Err(other_error(&format!("{}: directory not empty", path)));
+                }
+            }
+            Some(_) => return Err(other_error(&format!("{}: not a directory", path))),
+            None => return Err(not_found(path)),
+        }
+        children.remove(name);
+        Ok(())
+    }
+
+    fn rename(&self, src: &str, dst: &str) -> Result<()> {
+        let mut root = self.root.write().unwrap();
+        let src_is_dir = match root.get(src) {
+            Some(node) => node.metadata().kind == FileKind::Dir,
+            None => return Err(not_found(src)),
+        };
+        if components(dst).starts_with(&components(src)) {
+            return Err(other_error(&format!("{}: can't move into itself", src)));
+        }
+        {
+            let (children, name) = root.parent_mut(dst)?;
+            if let Some(&Node { content: Content::Dir(ref content), .. }) = children.get(name) {
+                if !src_is_dir || !content.is_empty() {
+                    return Err(other_error(&format!("{}: is a directory", dst)));
+                }
+            }
+        }
+        let node = {
+            let (children, name) = root.parent_mut(src)?;
+            children.remove(name).ok_or_else(|| not_found(src))?
+        };
+        let (children, name) = root.parent_mut(dst)?;
+        children.insert(name.to_owned(), node);
+        Ok(())
+    }
+
+}
+
+/// Split path into its non empty components
+fn components(path: &str) -> Vec<&str> {
+    path.split('/').filter(|name| !name.is_empty()).collect()
+}
+
+/// Get current time in seconds since epoch
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Build an io error for an invalid operation
+fn other_error(message: &str) -> Error {
+    io::Error::other(message.to_owned()).into()
+}
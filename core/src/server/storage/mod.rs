@@ -0,0 +1,227 @@
+//! Storage backends used by soft server to keep files
+mod dedup;
+mod local;
+mod memory;
+
+pub use self::dedup::{DedupStorage, DedupWriter, GcStats};
+pub use self::local::LocalStorage;
+pub use self::memory::{MemoryReader, MemoryStorage, MemoryWriter};
+
+use error::*;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use types::*;
+
+/// Prefix of hidden files receiving uploads
+pub const UPLOAD_PREFIX: &str = ".soft-upload.";
+
+/// Suffix of hidden files keeping interrupted uploads, named `<prefix><name><suffix>`
+pub const PARTIAL_SUFFIX: &str = ".partial";
+
+/// Age after which uploads of another process are removed, even if it still runs
+const UPLOAD_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Age after which interrupted uploads are removed instead of being kept for a resume
+pub const PARTIAL_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Backend storing files served by soft server
+///
+/// Paths given to a storage are absolute and normalized, `/` being the root of the
+/// storage. Failures are reported as `ErrorKind::Io`, with `NotFound` when a path doesn't
+/// exist.
+pub trait Storage: Send + Sync + 'static {
+    /// Reader returned when opening a file
+    type Reader: Read;
+    /// Writer returned when creating a file
+    type Writer: Write;
+
+    /// Open a file for reading, starting at offset
+    fn open(&self, path: &str, offset: u64) -> Result<Self::Reader>;
+
+    /// Create or truncate a file for writing
+    fn create(&self, path: &str) -> Result<Self::Writer>;
+
+    /// Finish writing a file, making its content durable
+    fn finish(&self, writer: Self::Writer) -> Result<()>;
+
+    /// List entries of a directory
+    fn list(&self, path: &str) -> Result<Vec<DirEntry>>;
+
+    /// Get metadata of a file, following symbolic links
+    fn stat(&self, path: &str) -> Result<Metadata>;
+
+    /// Get metadata of a file without following symbolic links
+    fn symlink_stat(&self, path: &str) -> Result<Metadata> {
+        self.stat(path)
+    }
+
+    /// Create a directory and all its parents
+    fn mkdir(&self, path: &str) -> Result<()>;
+
+    /// Remove a file
+    fn remove_file(&self, path: &str) -> Result<()>;
+
+    /// Remove a directory, with all its content if recursive is set
+    fn remove_dir(&self, path: &str, recursive: bool) -> Result<()>;
+
+    /// Move a file or a directory, replacing destination file if it exists
+    fn rename(&self, src: &str, dst: &str) -> Result<()>;
+
+    /// Resolve symbolic links in path, returning the path it really designates
+    ///
+    /// Fails with `ErrorKind::PermissionDenied` when a link leads outside of the storage.
+    /// Storages without symbolic links return path unchanged.
+    fn real_path(&self, path: &str) -> Result<String> {
+        Ok(path.to_owned())
+    }
+
+    /// Copy a file or a directory with all its content, following symbolic links
+    fn copy(&self, src: &str, dst: &str) -> Result<()> {
+        if self.stat(src)?.kind == FileKind::Dir {
+            self.mkdir(dst)?;
+            for entry in self.list(src)? {
+                self.copy(&format!("{}/{}", src, entry.name),
+                          &format!("{}/{}", dst, entry.name))?;
+            }
+            Ok(())
+        } else {
+            let mut reader = self.open(src, 0)?;
+            let mut writer = self.create(dst)?;
+            io::copy(&mut reader, &mut writer)?;
+            self.finish(writer)
+        }
+    }
+}
+
+/// Build an error for a path that doesn't exist
+pub fn not_found(path: &str) -> Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{}: no such file or directory", path)).into()
+}
+
+/// Check if error tells that a path doesn't exist
+fn is_not_found(e: &Error) -> bool {
+    match *e.kind() {
+        ErrorKind::Io(ref e) => e.kind() == io::ErrorKind::NotFound,
+        _ => false,
+    }
+}
+
+/// Read metadata of a file
+fn file_metadata(path: &Path, follow: bool) -> Result<Metadata> {
+    let metadata = if follow {
+        fs::metadata(path)?
+    } else {
+        fs::symlink_metadata(path)?
+    };
+    let file_type = metadata.file_type();
+    let kind = if file_type.is_symlink() {
+        FileKind::Symlink
+    } else if file_type.is_dir() {
+        FileKind::Dir
+    } else if file_type.is_file() {
+        FileKind::File
+    } else {
+        FileKind::Other
+    };
+    let symlink_target = if file_type.is_symlink() {
+        Some(fs::read_link(path)?.display().to_string())
+    } else {
+        None
+    };
+    // Times before 1970 are clamped to it
+    let modified = metadata.modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    Ok(Metadata {
+        kind,
+        size: metadata.len(),
+        modified,
+        permissions: permission_bits(&metadata),
+        symlink_target,
+    })
+}
+
+/// Get unix permission bits of a file
+#[cfg(unix)]
+fn permission_bits(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+/// Get unix permission bits of a file
+#[cfg(not(unix))]
+fn permission_bits(metadata: &fs::Metadata) -> u32 {
+    if metadata.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
+
+/// Remove uploads left behind by an interrupted server
+///
+/// Entries which can't be read are reported and skipped.
+fn remove_stale_uploads(dir: &Path) {
+    let entries = match dir.read_dir() {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Can't look for stale uploads in {}: {}", dir.display(), e);
+            return;
+        }
+    };
+    for entry in entries {
+        if let Err(e) = entry.and_then(|entry| remove_stale_entry(&entry)) {
+            eprintln!("Can't look for stale uploads in {}: {}", dir.display(), e);
+        }
+    }
+}
+
+/// Remove entry if it is a stale upload, or stale uploads inside it if it is a directory
+fn remove_stale_entry(entry: &fs::DirEntry) -> io::Result<()> {
+    if entry.file_type()?.is_dir() {
+        remove_stale_uploads(&entry.path());
+        return Ok(());
+    }
+    let name = entry.file_name().to_string_lossy().into_owned();
+    if !name.starts_with(UPLOAD_PREFIX) {
+        return Ok(());
+    }
+    let modified = entry.metadata()?.modified()?;
+    let age = SystemTime::now().duration_since(modified).unwrap_or_default();
+    if is_stale_upload(&name, age) {
+        fs::remove_file(entry.path())?;
+    }
+    Ok(())
+}
+
+/// Check if an upload file, last modified age ago, was abandoned by its server
+///
+/// Uploads are named `<prefix><name>.<pid>.<id>`. Those of another process are stale once
+/// it stopped, or after `UPLOAD_MAX_AGE` when this can't be known. Interrupted ones are
+/// kept for `PARTIAL_MAX_AGE`.
+fn is_stale_upload(name: &str, age: Duration) -> bool {
+    if name.ends_with(PARTIAL_SUFFIX) {
+        return age > PARTIAL_MAX_AGE;
+    }
+    match name.rsplit('.').nth(1).and_then(|pid| pid.parse::<u32>().ok()) {
+        Some(pid) if pid == process::id() => false,
+        Some(pid) => !is_running(pid) || age > UPLOAD_MAX_AGE,
+        None => true,
+    }
+}
+
+/// Check if a process is running
+#[cfg(target_os = "linux")]
+fn is_running(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+/// Check if a process is running, which is always assumed
+#[cfg(not(target_os = "linux"))]
+fn is_running(_pid: u32) -> bool {
+    true
+}
@@ -1,66 +1,564 @@
 //! User module to handle users
 use error::*;
+use scram;
+use super::auth::{Authenticator, Identity, Permissions};
 use std::collections::HashMap;
-use std::fs::File;
+use std::fmt;
+use std::fs::{self, File};
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Number of PBKDF2 iterations used to hash new passwords
+pub const PBKDF2_ITERATIONS: u32 = 100_000;
+
+/// Size of salt used to hash new passwords
+const SALT_SIZE: usize = 16;
+
+/// Keys derived from a password, enough to check it without knowing it
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScramKeys {
+    /// Number of PBKDF2 iterations
+    pub iterations: u32,
+    /// Random salt
+    pub salt: Vec<u8>,
+    /// Hash of the key proving client knows password
+    pub stored_key: Vec<u8>,
+    /// Key proving server knows password hash
+    pub server_key: Vec<u8>,
+}
+
+impl ScramKeys {
+    /// Derive keys from a PBKDF2-HMAC-SHA256 hash of password
+    pub fn from_salted_password(salted_password: &[u8], salt: Vec<u8>, iterations: u32) -> ScramKeys {
+        ScramKeys {
+            iterations,
+            salt,
+            stored_key: scram::stored_key(&scram::client_key(salted_password)),
+            server_key: scram::server_key(salted_password),
+        }
+    }
+
+    /// Derive keys matching no password for an unknown user
+    ///
+    /// Keys come from the name of user and a random secret of the process, so that the
+    /// same user always gets the same salt, like known users do.
+    pub fn fake(user: &str) -> Result<ScramKeys> {
+        static SECRET: OnceLock<Vec<u8>> = OnceLock::new();
+        if SECRET.get().is_none() {
+            let _ = SECRET.set(scram::random_bytes(scram::KEY_SIZE)?);
+        }
+        let secret = SECRET.get().unwrap();
+        let derive = |label: &str| scram::hmac(secret, format!("{}:{}", label, user).as_bytes());
+        Ok(ScramKeys {
+            iterations: PBKDF2_ITERATIONS,
+            salt: derive("salt")[..SALT_SIZE].to_vec(),
+            stored_key: derive("stored key"),
+            server_key: derive("server key"),
+        })
+    }
+}
+
+/// Keys derived from plaintext passwords, kept so that their salt doesn't change
+#[derive(Clone, Debug, Default)]
+pub struct KeyCache(Arc<Mutex<HashMap<String, (String, ScramKeys)>>>);
+
+impl KeyCache {
+    /// Get keys of password of user, hashing a plaintext password only once
+    pub fn scram_keys(&self, user: &str, password: &Password) -> Option<ScramKeys> {
+        let plain = match *password {
+            Password::Plain(ref plain) => plain,
+            _ => return password.scram_keys().ok(),
+        };
+        if let Some((cached, keys)) = self.0.lock().unwrap().get(user) {
+            if cached == plain {
+                return Some(keys.clone());
+            }
+        }
+        let keys = password.scram_keys().ok()?;
+        self.0.lock().unwrap().insert(user.to_owned(), (plain.clone(), keys.clone()));
+        Some(keys)
+    }
+}
+
+/// Password of a user as kept in database
+///
+/// Records are written as `$<version>$<scheme>$<parameters>` with hexadecimal binary
+/// parameters:
+///
+/// - version 1 is `$1$pbkdf2-sha256$<iterations>$<salt>$<hash>`
+/// - version 2 is `$2$scram-sha-256$<iterations>$<salt>$<stored key>$<server key>`
+///
+/// Anything else is an old plaintext password.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Password {
+    /// Plaintext password of old databases
+    Plain(String),
+    /// Salted PBKDF2-HMAC-SHA256 hash
+    Pbkdf2 {
+        /// Number of iterations
+        iterations: u32,
+        /// Random salt
+        salt: Vec<u8>,
+        /// Derived key
+        hash: Vec<u8>,
+    },
+    /// Keys usable by challenge-response authentication
+    Scram(ScramKeys),
+}
+
+impl Password {
+    /// Hash password with a new random salt
+    pub fn hash(pass: &str) -> Result<Password> {
+        let salt = scram::random_bytes(SALT_SIZE)?;
+        Ok(Password::hash_with(pass, salt, PBKDF2_ITERATIONS))
+    }
+
+    /// Hash password with salt and number of iterations
+    pub fn hash_with(pass: &str, salt: Vec<u8>, iterations: u32) -> Password {
+        let salted_password = scram::salted_password(pass, &salt, iterations);
+        Password::Scram(ScramKeys::from_salted_password(&salted_password, salt, iterations))
+    }
+
+    /// Parse a password record
+    pub fn try_from<S: AsRef<str>>(s: S) -> Result<Password> {
+        let s = s.as_ref();
+        if !s.starts_with("$1$") && !s.starts_with("$2$") {
+            return Ok(Password::Plain(s.to_owned()));
+        }
+        let fields = s.split('$').skip(2).collect::<Vec<&str>>();
+        let scheme = (&s[1..2], fields[0]);
+        if fields.len() < 4 {
+            bail!(ErrorKind::InvalidUserRecord);
+        }
+        let iterations = fields[1].parse()?;
+        let params = match fields[2..].iter().map(|f| ::common::from_hex(f)).collect() {
+            Some(params) => params,
+            None => bail!(ErrorKind::InvalidUserRecord),
+        };
+        let mut params: Vec<Vec<u8>> = params;
+        match (scheme, params.len()) {
+            (("1", "pbkdf2-sha256"), 2) => {
+                Ok(Password::Pbkdf2 {
+                    iterations,
+                    hash: params.remove(1),
+                    salt: params.remove(0),
+                })
+            }
+            (("2", "scram-sha-256"), 3) => {
+                Ok(Password::Scram(ScramKeys {
+                    iterations,
+                    server_key: params.remove(2),
+                    stored_key: params.remove(1),
+                    salt: params.remove(0),
+                }))
+            }
+            _ => bail!(ErrorKind::InvalidUserRecord),
+        }
+    }
+
+    /// Get a hashed password matching no pass
+    fn dummy() -> Password {
+        Password::Scram(ScramKeys {
+            iterations: PBKDF2_ITERATIONS,
+            salt: vec![0; SALT_SIZE],
+            stored_key: vec![0; scram::KEY_SIZE],
+            server_key: vec![0; scram::KEY_SIZE],
+        })
+    }
+
+    /// Check if pass matches this password, in constant time for hashed passwords
+    pub fn verify(&self, pass: &str) -> bool {
+        match *self {
+            Password::Plain(ref plain) => {
+                ::common::constant_time_eq(plain.as_bytes(), pass.as_bytes())
+            }
+            Password::Pbkdf2 { iterations, ref salt, ref hash } => {
+                let salted_password = scram::salted_password(pass, salt, iterations);
+                ::common::constant_time_eq(&salted_password, hash)
+            }
+            Password::Scram(ref keys) => {
+                let salted_password = scram::salted_password(pass, &keys.salt, keys.iterations);
+                let stored_key = scram::stored_key(&scram::client_key(&salted_password));
+                ::common::constant_time_eq(&stored_key, &keys.stored_key)
+            }
+        }
+    }
+
+    /// Check if password should be hashed again with the current scheme
+    pub fn is_outdated(&self) -> bool {
+        match *self {
+            Password::Scram(ref keys) => keys.iterations < PBKDF2_ITERATIONS,
+            _ => true,
+        }
+    }
+
+    /// Get keys for challenge-response authentication
+    ///
+    /// Plaintext passwords are hashed with a new salt.
+    pub fn scram_keys(&self) -> Result<ScramKeys> {
+        match *self {
+            Password::Plain(ref plain) => {
+                let salt = scram::random_bytes(SALT_SIZE)?;
+                let salted_password = scram::salted_password(plain, &salt, PBKDF2_ITERATIONS);
+                Ok(ScramKeys::from_salted_password(&salted_password, salt, PBKDF2_ITERATIONS))
+            }
+            Password::Pbkdf2 { iterations, ref salt, ref hash } => {
+                Ok(ScramKeys::from_salted_password(hash, salt.clone(), iterations))
+            }
+            Password::Scram(ref keys) => Ok(keys.clone()),
+        }
+    }
+}
+
+impl fmt::Display for Password {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Password::Plain(ref plain) => write!(f, "{}", plain),
+            Password::Pbkdf2 { iterations, ref salt, ref hash } => {
+                write!(f,
+                       "$1$pbkdf2-sha256${}${}${}",
+                       iterations,
+                       ::common::to_hex(salt),
+                       ::common::to_hex(hash))
+            }
+            Password::Scram(ref keys) => {
+                write!(f,
+                       "$2$scram-sha-256${}${}${}${}",
+                       keys.iterations,
+                       ::common::to_hex(&keys.salt),
+                       ::common::to_hex(&keys.stored_key),
+                       ::common::to_hex(&keys.server_key))
+            }
+        }
+    }
+}
+
+/// Entry of a user in database
+#[derive(Clone, Debug)]
+struct User {
+    password: Password,
+    permissions: Permissions,
+}
 
 /// Database of user
-pub struct Users(Arc<Mutex<HashMap<String, String>>>, PathBuf);
+///
+/// Each line of database file is `<user> <password> [<permissions>]`, users without
+/// permissions being allowed everything. Every change is saved at once, by writing a
+/// temporary file which then replaces the database, so that a crash can't lose it.
+pub struct Users(Arc<Mutex<HashMap<String, User>>>, Option<PathBuf>, KeyCache);
 
 impl Users {
-    /// Load a database or create a new one if path doesn't exists
+    /// Create an empty database which is never saved
+    pub fn new() -> Users {
+        Users(Arc::new(Mutex::new(HashMap::new())), None, KeyCache::default())
+    }
+
+    /// Load `users.db` database from dir or create a new one if it doesn't exists
     pub fn load<P: AsRef<Path>>(dir: P) -> Result<Users> {
-        let path = dir.as_ref().join("users.db");
+        Users::load_file(dir.as_ref().join("users.db"))
+    }
+
+    /// Load a database or create a new one if path doesn't exists
+    ///
+    /// Fails with `ErrorKind::InvalidUserDB` giving the number of the first invalid line.
+    pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Users> {
+        let path = path.as_ref();
         if !path.exists() {
-            return Ok(Users(Arc::new(Mutex::new(HashMap::new())), path.to_path_buf()));
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            return Ok(Users(Arc::new(Mutex::new(HashMap::new())),
+                            Some(path.to_path_buf()),
+                            KeyCache::default()));
         }
-        let mut file = File::open(&path)?;
+        let mut file = File::open(path)?;
         let mut buf = String::new();
         file.read_to_string(&mut buf)?;
         let mut db = HashMap::new();
-        for line in buf.split('\n') {
+        for (i, line) in buf.lines().enumerate() {
+            let line = line.trim();
             if line.is_empty() {
                 continue;
             }
-            let line = line.trim();
-            let words = line.split_whitespace().map(|x| x.to_owned()).collect::<Vec<String>>();
-            if words.len() > 2 {
-                bail!(ErrorKind::InvalidUserDB);
-            }
-            db.insert(words[0].clone(), words[1].clone());
+            let (user, entry) = parse_line(line).chain_err(|| ErrorKind::InvalidUserDB(i + 1))?;
+            db.insert(user, entry);
         }
 
-        Ok(Users(Arc::new(Mutex::new(db)), path.to_path_buf()))
+        Ok(Users(Arc::new(Mutex::new(db)), Some(path.to_path_buf()), KeyCache::default()))
     }
 
     /// Check if the user provided is present and if his password is valid.
+    ///
+    /// Unknown users are checked against a dummy hash, so that they take as long as known
+    /// ones. Outdated passwords are hashed again with the current scheme once validated. If
+    /// the new record can't be saved, the old one is kept, which is still valid.
     pub fn is_valid(&self, user: &str, pass: &str) -> bool {
+        let password = match self.password(user) {
+            Some(password) => password,
+            None => {
+                Password::dummy().verify(pass);
+                return false;
+            }
+        };
+        if !password.verify(pass) {
+            return false;
+        }
+        if password.is_outdated() {
+            if let Ok(password) = Password::hash(pass) {
+                let _ = self.update(|db| if let Some(entry) = db.get_mut(user) {
+                    entry.password = password;
+                });
+            }
+        }
+        true
+    }
+
+    /// Add a new user allowed everything to database
+    ///
+    /// Fails with `ErrorKind::UserExists` if there is already a user with this name.
+    pub fn add_user(&self, user: &str, pass: &str) -> Result<()> {
+        self.add_user_with(user, pass, Permissions::all())
+    }
+
+    /// Add a new user allowed some operations to database
+    ///
+    /// Fails with `ErrorKind::UserExists` if there is already a user with this name, and
+    /// with `ErrorKind::InvalidUserName` if name is empty, `.`, `..` or holds whitespace,
+    /// control characters or `/`.
+    pub fn add_user_with(&self, user: &str, pass: &str, permissions: Permissions) -> Result<()> {
+        check_name(user)?;
+        let password = Password::hash(pass)?;
+        self.update(|db| {
+            if db.contains_key(user) {
+                bail!(ErrorKind::UserExists(user.to_owned()));
+            }
+            db.insert(user.to_owned(),
+                      User {
+                          password,
+                          permissions,
+                      });
+            Ok(())
+        })?
+    }
+
+    /// Remove a user from database, returning false if there is no such user
+    pub fn remove_user(&self, user: &str) -> Result<bool> {
+        self.update(|db| db.remove(user).is_some())
+    }
+
+    /// Change password of user, returning false if there is no such user
+    pub fn set_password(&self, user: &str, pass: &str) -> Result<bool> {
+        let password = Password::hash(pass)?;
+        self.update(|db| match db.get_mut(user) {
+            Some(entry) => {
+                entry.password = password;
+                true
+            }
+            None => false,
+        })
+    }
+
+    /// List identities of all users, sorted by name
+    pub fn list(&self) -> Vec<Identity> {
         let lock = self.0.lock().unwrap();
-        match lock.get(user) {
-            Some(p) => p == pass,
+        let mut list = lock.iter()
+            .map(|(user, entry)| {
+                Identity {
+                    permissions: entry.permissions,
+                    ..Identity::new(user)
+                }
+            })
+            .collect::<Vec<Identity>>();
+        list.sort_by(|a, b| a.user.cmp(&b.user));
+        list
+    }
+
+    /// Get password record of user
+    pub fn password(&self, user: &str) -> Option<Password> {
+        self.0.lock().unwrap().get(user).map(|entry| entry.password.clone())
+    }
+
+    /// Get operations allowed to user
+    pub fn permissions(&self, user: &str) -> Option<Permissions> {
+        self.0.lock().unwrap().get(user).map(|entry| entry.permissions)
+    }
+
+    /// Set operations allowed to user, returning false if there is no such user
+    pub fn set_permissions(&self, user: &str, permissions: Permissions) -> Result<bool> {
+        self.update(|db| match db.get_mut(user) {
+            Some(entry) => {
+                entry.permissions = permissions;
+                true
+            }
             None => false,
+        })
+    }
+
+    /// Get keys to authenticate user with a challenge
+    pub fn scram_keys(&self, user: &str) -> Option<ScramKeys> {
+        self.password(user).and_then(|p| self.2.scram_keys(user, &p))
+    }
+
+    /// Replace outdated password record of user by keys which authenticated it
+    ///
+    /// Like in `is_valid`, the old record is kept if the new one can't be saved.
+    pub fn upgrade(&self, user: &str, keys: ScramKeys) {
+        let password = Password::Scram(keys);
+        if password.is_outdated() {
+            return;
         }
+        let _ = self.update(|db| if let Some(entry) = db.get_mut(user) {
+            if entry.password.is_outdated() {
+                entry.password = password;
+            }
+        });
     }
 
-    /// Add a new user to database
-    pub fn add_user(&self, user: &str, pass: &str) {
+    /// Apply a change to a copy of database, which replaces it once saved
+    fn update<T, F>(&self, change: F) -> Result<T>
+        where F: FnOnce(&mut HashMap<String, User>) -> T
+    {
         let mut lock = self.0.lock().unwrap();
-        if lock.get(user).is_some() {
-            return;
+        let mut db = lock.clone();
+        let result = change(&mut db);
+        self.save(&db)?;
+        *lock = db;
+        Ok(result)
+    }
+
+    /// Write database to a temporary file and move it over database file
+    ///
+    /// File and directory are synced, so that a saved database survives a crash.
+    fn save(&self, db: &HashMap<String, User>) -> Result<()> {
+        let path = match self.1 {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        let mut users = db.iter().collect::<Vec<_>>();
+        users.sort_by(|a, b| a.0.cmp(b.0));
+        let mut content = String::new();
+        for (user, entry) in users {
+            if entry.permissions == Permissions::all() {
+                content.push_str(&format!("{} {}\n", user, entry.password));
+            } else {
+                content.push_str(&format!("{} {} {}\n", user, entry.password, entry.permissions));
+            }
         }
-        lock.insert(user.to_owned(), pass.to_owned());
+        let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = path.with_file_name(tmp_name);
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        sync_dir(path)
     }
 }
 
-impl Drop for Users {
-    fn drop(&mut self) {
-        let lock = self.0.lock().unwrap();
-        let mut file = File::create(&self.1).unwrap();
-        for (key, value) in lock.iter() {
-            let s = format!("{} {}\n", key, value);
-            let _ = file.write_all(s.as_bytes());
+/// Make the rename of a file in its directory durable
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Make the rename of a file in its directory durable, which can't be done on this platform
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Generate a random password, for accounts created without one
+pub fn random_password() -> Result<String> {
+    scram::nonce()
+}
+
+/// Check that a user name can be saved in a database line and used in a home path
+fn check_name(user: &str) -> Result<()> {
+    if user.is_empty() || user == "." || user == ".." ||
+       user.chars().any(|c| c.is_whitespace() || c.is_control() || c == '/') {
+        bail!(ErrorKind::InvalidUserName(user.to_owned()));
+    }
+    Ok(())
+}
+
+/// Parse a line of database into name and entry of user
+fn parse_line(line: &str) -> Result<(String, User)> {
+    let words = line.split_whitespace().collect::<Vec<&str>>();
+    if words.len() < 2 || words.len() > 3 {
+        bail!(ErrorKind::InvalidUserRecord);
+    }
+    let permissions = match words.get(2) {
+        Some(permissions) => Permissions::try_from(permissions)?,
+        None => Permissions::all(),
+    };
+    let user = User {
+        password: Password::try_from(words[1])?,
+        permissions,
+    };
+    Ok((words[0].to_owned(), user))
+}
+
+
+impl Authenticator for Users {
+    fn authenticate(&self, user: &str, pass: &str) -> Option<Identity> {
+        if self.is_valid(user, pass) {
+            self.identity(user)
+        } else {
+            None
+        }
+    }
+
+    fn supports_scram(&self) -> bool {
+        true
+    }
+
+    fn scram_keys(&self, user: &str) -> Option<ScramKeys> {
+        Users::scram_keys(self, user)
+    }
+
+    fn identity(&self, user: &str) -> Option<Identity> {
+        self.permissions(user).map(|permissions| {
+            Identity {
+                permissions,
+                ..Identity::new(user)
+            }
+        })
+    }
+
+    fn upgrade(&self, user: &str, keys: ScramKeys) {
+        Users::upgrade(self, user, keys)
+    }
+
+    fn add_user(&self, user: &str, pass: &str, permissions: Permissions) -> Result<()> {
+        self.add_user_with(user, pass, permissions)
+    }
+
+    fn remove_user(&self, user: &str) -> Result<()> {
+        if !Users::remove_user(self, user)? {
+            bail!(ErrorKind::UserUnknown(user.to_owned()));
         }
+        Ok(())
+    }
+
+    fn set_password(&self, user: &str, pass: &str) -> Result<()> {
+        if !Users::set_password(self, user, pass)? {
+            bail!(ErrorKind::UserUnknown(user.to_owned()));
+        }
+        Ok(())
+    }
+
+    fn list_users(&self) -> Result<Vec<Identity>> {
+        Ok(self.list())
+    }
+}
+
+impl Default for Users {
+    fn default() -> Users {
+        Users::new()
     }
 }
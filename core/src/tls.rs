@@ -0,0 +1,121 @@
+//! TLS transport for soft clients and servers
+//!
+//! Streams returned by `accept` and `connect` can be handed to `SoftServer::new_connection`
+//! and `SoftClient::new` like any other stream, the handshake happens on first use.
+//!
+//! ```no_run
+//! use soft_core::client::SoftClient;
+//! use soft_core::tls;
+//! use std::net::TcpStream;
+//!
+//! let config = tls::client_config_from_file(Some("ca.pem")).unwrap();
+//! let stream = TcpStream::connect("127.0.0.1:9045").unwrap();
+//! let stream = tls::connect(&config, "127.0.0.1", stream).unwrap();
+//! let mut client = SoftClient::connect(stream).unwrap();
+//! ```
+use error::*;
+use rustls::{ClientConnection, RootCertStore, ServerConnection, StreamOwned};
+use rustls::crypto::ring;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+pub use rustls::{ClientConfig, ServerConfig};
+
+/// Stream of a server encrypted with TLS
+pub type ServerStream<S> = StreamOwned<ServerConnection, S>;
+
+/// Stream of a client encrypted with TLS
+pub type ClientStream<S> = StreamOwned<ClientConnection, S>;
+
+/// Read all certificates of a PEM file
+pub fn load_certs<P: AsRef<Path>>(path: P) -> Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(File::open(path.as_ref())?);
+    let certs = rustls_pemfile::certs(&mut reader).collect::<::std::io::Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        bail!(ErrorKind::Tls(format!("no certificate in {}", path.as_ref().display())));
+    }
+    Ok(certs)
+}
+
+/// Read first private key of a PEM file
+pub fn load_key<P: AsRef<Path>>(path: P) -> Result<PrivateKeyDer<'static>> {
+    let mut reader = BufReader::new(File::open(path.as_ref())?);
+    match rustls_pemfile::private_key(&mut reader)? {
+        Some(key) => Ok(key),
+        None => bail!(ErrorKind::Tls(format!("no private key in {}", path.as_ref().display()))),
+    }
+}
+
+/// Build server configuration presenting a certificate chain
+pub fn server_config(certs: Vec<CertificateDer<'static>>,
+                     key: PrivateKeyDer<'static>)
+                     -> Result<Arc<ServerConfig>> {
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(tls_error)?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(tls_error)?;
+    Ok(Arc::new(config))
+}
+
+/// Build server configuration from PEM files of certificate chain and private key
+pub fn server_config_from_files<P: AsRef<Path>, Q: AsRef<Path>>(cert: P,
+                                                                key: Q)
+                                                                -> Result<Arc<ServerConfig>> {
+    server_config(load_certs(cert)?, load_key(key)?)
+}
+
+/// Build client configuration trusting certificate authorities
+///
+/// Well-known authorities of the web are trusted if none is given.
+pub fn client_config(authorities: Vec<CertificateDer<'static>>) -> Result<Arc<ClientConfig>> {
+    let mut roots = RootCertStore::empty();
+    if authorities.is_empty() {
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    }
+    for cert in authorities {
+        roots.add(cert).map_err(tls_error)?;
+    }
+    let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(tls_error)?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+/// Build client configuration trusting authorities of a PEM file, or well-known ones
+pub fn client_config_from_file<P: AsRef<Path>>(ca: Option<P>) -> Result<Arc<ClientConfig>> {
+    match ca {
+        Some(ca) => client_config(load_certs(ca)?),
+        None => client_config(Vec::new()),
+    }
+}
+
+/// Wrap stream of a client connected to server
+pub fn accept<S: Read + Write>(config: &Arc<ServerConfig>, stream: S) -> Result<ServerStream<S>> {
+    let connection = ServerConnection::new(config.clone()).map_err(tls_error)?;
+    Ok(StreamOwned::new(connection, stream))
+}
+
+/// Wrap stream connected to server, checking its certificate is valid for server name
+///
+/// Server name is a DNS name or an IP address.
+pub fn connect<S: Read + Write>(config: &Arc<ClientConfig>,
+                                server_name: &str,
+                                stream: S)
+                                -> Result<ClientStream<S>> {
+    let name = ServerName::try_from(server_name.to_owned()).map_err(tls_error)?;
+    let connection = ClientConnection::new(config.clone(), name).map_err(tls_error)?;
+    Ok(StreamOwned::new(connection, stream))
+}
+
+/// Convert any TLS error
+fn tls_error<E: ::std::fmt::Display>(e: E) -> Error {
+    ErrorKind::Tls(e.to_string()).into()
+}
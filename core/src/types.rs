@@ -27,13 +27,46 @@ pub enum Command {
     Presence,
     /// Exit
     Exit,
+    /// Handshake with protocol version and supported capabilities
+    Hello(u32, Vec<Capability>),
+    /// Get a file starting at offset
+    GetAt(String, u64),
+    /// Put a file starting at offset, continuing its interrupted upload
+    PutAt(String, u64),
+    /// Get size of a file
+    Size(String),
+    /// Get metadata of a file
+    Stat(String),
+    /// List directory with metadata of every entry
+    ListLong(String),
+    /// Move or rename a file or directory
+    Mv(String, String),
+    /// Copy a file or a directory
+    Cp(String, String, bool),
+    /// Start challenge-response login with user name and client nonce
+    Auth(String, String),
+    /// Prove knowledge of password with hexadecimal client proof
+    Proof(String),
+    /// Add a user with password and permissions, if given
+    UserAdd(String, String, Option<Permissions>),
+    /// Remove a user
+    UserDel(String),
+    /// Change password of a user, or of the logged in user if none is given
+    Passwd(Option<String>, String),
+    /// List users with their permissions
+    Users,
+    /// Get size of the interrupted upload kept for a file
+    Resume(String),
 }
 
 impl Command {
     /// Try converting string to command
     pub fn try_from<S: AsRef<str>>(s: S) -> Result<Command> {
         let s = s.as_ref().to_string();
-        let splitted = s.split_whitespace().map(|s| s.to_owned()).collect::<Vec<String>>();
+        let splitted = split_args(&s)?;
+        if splitted.is_empty() {
+            bail!(ErrorKind::InvalidCommand(s));
+        }
         match splitted[0].as_str() {
             "LOGIN" => {
                 if splitted.len() != 3 {
@@ -41,23 +74,97 @@ impl Command {
                 }
                 Ok(Command::Login(splitted[1].clone(), splitted[2].clone()))
             }
-            "GET" => {
+            "AUTH" => {
+                if splitted.len() != 3 {
+                    bail!(ErrorKind::InvalidCommand(s));
+                }
+                Ok(Command::Auth(splitted[1].clone(), splitted[2].clone()))
+            }
+            "PROOF" => {
+                if splitted.len() != 2 {
+                    bail!(ErrorKind::InvalidCommand(s));
+                }
+                Ok(Command::Proof(splitted[1].clone()))
+            }
+            "USERADD" => {
+                match splitted.len() {
+                    3 => Ok(Command::UserAdd(splitted[1].clone(), splitted[2].clone(), None)),
+                    4 => {
+                        let permissions = match Permissions::try_from(&splitted[3]) {
+                            Ok(p) => p,
+                            Err(_) => bail!(ErrorKind::InvalidCommand(s)),
+                        };
+                        Ok(Command::UserAdd(splitted[1].clone(),
+                                            splitted[2].clone(),
+                                            Some(permissions)))
+                    }
+                    _ => bail!(ErrorKind::InvalidCommand(s)),
+                }
+            }
+            "USERDEL" => {
+                if splitted.len() != 2 {
+                    bail!(ErrorKind::InvalidCommand(s));
+                }
+                Ok(Command::UserDel(splitted[1].clone()))
+            }
+            "PASSWD" => {
+                match splitted.len() {
+                    2 => Ok(Command::Passwd(None, splitted[1].clone())),
+                    3 => Ok(Command::Passwd(Some(splitted[1].clone()), splitted[2].clone())),
+                    _ => bail!(ErrorKind::InvalidCommand(s)),
+                }
+            }
+            "USERS" => Ok(Command::Users),
+            "RESUME" => {
                 if splitted.len() != 2 {
                     bail!(ErrorKind::InvalidCommand(s));
                 }
-                Ok(Command::Get(splitted[1].clone()))
+                Ok(Command::Resume(splitted[1].clone()))
+            }
+            "GET" => {
+                match splitted.len() {
+                    2 => Ok(Command::Get(splitted[1].clone())),
+                    3 => {
+                        let offset = match splitted[2].parse::<u64>() {
+                            Ok(o) => o,
+                            Err(_) => bail!(ErrorKind::InvalidCommand(s)),
+                        };
+                        Ok(Command::GetAt(splitted[1].clone(), offset))
+                    }
+                    _ => bail!(ErrorKind::InvalidCommand(s)),
+                }
             }
             "PUT" => {
+                match splitted.len() {
+                    2 => Ok(Command::Put(splitted[1].clone())),
+                    3 => {
+                        let offset = match splitted[2].parse::<u64>() {
+                            Ok(o) => o,
+                            Err(_) => bail!(ErrorKind::InvalidCommand(s)),
+                        };
+                        Ok(Command::PutAt(splitted[1].clone(), offset))
+                    }
+                    _ => bail!(ErrorKind::InvalidCommand(s)),
+                }
+            }
+            "SIZE" => {
                 if splitted.len() != 2 {
                     bail!(ErrorKind::InvalidCommand(s));
                 }
-                Ok(Command::Put(splitted[1].clone()))
+                Ok(Command::Size(splitted[1].clone()))
             }
-            "LIST" => {
+            "STAT" => {
                 if splitted.len() != 2 {
                     bail!(ErrorKind::InvalidCommand(s));
                 }
-                Ok(Command::List(splitted[1].clone()))
+                Ok(Command::Stat(splitted[1].clone()))
+            }
+            "LIST" => {
+                match splitted.len() {
+                    2 => Ok(Command::List(splitted[1].clone())),
+                    3 if splitted[2] == "LONG" => Ok(Command::ListLong(splitted[1].clone())),
+                    _ => bail!(ErrorKind::InvalidCommand(s)),
+                }
             }
             "CWD" => Ok(Command::Cwd),
             "CD" => {
@@ -82,11 +189,41 @@ impl Command {
                 if splitted.len() != 3 {
                     bail!(ErrorKind::InvalidCommand(s));
                 }
-                Ok(Command::Rmdir(splitted[1].clone(),
-                                  splitted[2].clone().parse::<bool>().unwrap()))
+                let recursive = match splitted[2].parse::<bool>() {
+                    Ok(r) => r,
+                    Err(_) => bail!(ErrorKind::InvalidCommand(s)),
+                };
+                Ok(Command::Rmdir(splitted[1].clone(), recursive))
+            }
+            "MV" => {
+                if splitted.len() != 3 {
+                    bail!(ErrorKind::InvalidCommand(s));
+                }
+                Ok(Command::Mv(splitted[1].clone(), splitted[2].clone()))
+            }
+            "CP" => {
+                if splitted.len() != 4 {
+                    bail!(ErrorKind::InvalidCommand(s));
+                }
+                let recursive = match splitted[3].parse::<bool>() {
+                    Ok(r) => r,
+                    Err(_) => bail!(ErrorKind::InvalidCommand(s)),
+                };
+                Ok(Command::Cp(splitted[1].clone(), splitted[2].clone(), recursive))
             }
             "PRESENCE" => Ok(Command::Presence),
             "EXIT" => Ok(Command::Exit),
+            "HELLO" => {
+                if splitted.len() < 2 {
+                    bail!(ErrorKind::InvalidCommand(s));
+                }
+                let version = match splitted[1].parse::<u32>() {
+                    Ok(v) => v,
+                    Err(_) => bail!(ErrorKind::InvalidCommand(s)),
+                };
+                let capabilities = splitted[2..].iter().map(|c| Capability::from(c.as_str())).collect();
+                Ok(Command::Hello(version, capabilities))
+            }
             _ => bail!(ErrorKind::InvalidCommand(s)),
         }
     }
@@ -101,11 +238,16 @@ impl Command {
     }
 
     /// Get the path from command,
-    /// Work for Get, Put and List, else it will panic
+    /// Work for every command working on a path, else it will panic
     pub fn unwrap_path(self) -> String {
         match self {
             Command::Get(s) |
             Command::Put(s) |
+            Command::GetAt(s, _) |
+            Command::PutAt(s, _) |
+            Command::Resume(s) |
+            Command::Size(s) |
+            Command::Stat(s) |
             Command::List(s) |
             Command::Cd(s) |
             Command::Rm(s) |
@@ -119,22 +261,426 @@ impl Command {
 impl fmt::Display for Command {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Command::Login(ref u, ref p) => write!(f, "LOGIN {} {}", u, p),
-            Command::Get(ref p) => write!(f, "GET {}", p),
-            Command::Put(ref p) => write!(f, "PUT {}", p),
-            Command::List(ref p) => write!(f, "LIST {}", p),
+            Command::Login(ref u, ref p) => write!(f, "LOGIN {} {}", quote_arg(u), quote_arg(p)),
+            Command::Get(ref p) => write!(f, "GET {}", quote_arg(p)),
+            Command::Put(ref p) => write!(f, "PUT {}", quote_arg(p)),
+            Command::List(ref p) => write!(f, "LIST {}", quote_arg(p)),
             Command::Cwd => write!(f, "CWD"),
-            Command::Cd(ref p) => write!(f, "CD {}", p),
-            Command::Mkdir(ref p) => write!(f, "MKDIR {}", p),
-            Command::Rm(ref p) => write!(f, "RM {}", p),
-            Command::Rmdir(ref p, ref r) => write!(f, "RMDIR {} {}", p, r),
+            Command::Cd(ref p) => write!(f, "CD {}", quote_arg(p)),
+            Command::Mkdir(ref p) => write!(f, "MKDIR {}", quote_arg(p)),
+            Command::Rm(ref p) => write!(f, "RM {}", quote_arg(p)),
+            Command::Rmdir(ref p, ref r) => write!(f, "RMDIR {} {}", quote_arg(p), r),
             Command::Presence => write!(f, "PRESENCE"),
             Command::Exit => write!(f, "EXIT"),
+            Command::Hello(ref v, ref c) => {
+                write!(f, "HELLO {}", v)?;
+                for capability in c {
+                    write!(f, " {}", quote_arg(&capability.to_string()))?;
+                }
+                Ok(())
+            }
+            Command::GetAt(ref p, ref o) => write!(f, "GET {} {}", quote_arg(p), o),
+            Command::PutAt(ref p, ref o) => write!(f, "PUT {} {}", quote_arg(p), o),
+            Command::Size(ref p) => write!(f, "SIZE {}", quote_arg(p)),
+            Command::Stat(ref p) => write!(f, "STAT {}", quote_arg(p)),
+            Command::ListLong(ref p) => write!(f, "LIST {} LONG", quote_arg(p)),
+            Command::Mv(ref s, ref d) => write!(f, "MV {} {}", quote_arg(s), quote_arg(d)),
+            Command::Cp(ref s, ref d, ref r) => {
+                write!(f, "CP {} {} {}", quote_arg(s), quote_arg(d), r)
+            }
+            Command::Auth(ref u, ref n) => write!(f, "AUTH {} {}", quote_arg(u), quote_arg(n)),
+            Command::Proof(ref p) => write!(f, "PROOF {}", quote_arg(p)),
+            Command::UserAdd(ref u, ref p, None) => {
+                write!(f, "USERADD {} {}", quote_arg(u), quote_arg(p))
+            }
+            Command::UserAdd(ref u, ref p, Some(ref perms)) => {
+                write!(f, "USERADD {} {} {}", quote_arg(u), quote_arg(p), perms)
+            }
+            Command::UserDel(ref u) => write!(f, "USERDEL {}", quote_arg(u)),
+            Command::Passwd(None, ref p) => write!(f, "PASSWD {}", quote_arg(p)),
+            Command::Passwd(Some(ref u), ref p) => {
+                write!(f, "PASSWD {} {}", quote_arg(u), quote_arg(p))
+            }
+            Command::Users => write!(f, "USERS"),
+            Command::Resume(ref p) => write!(f, "RESUME {}", quote_arg(p)),
+        }
+    }
+}
+
+/// Split a line into arguments
+///
+/// Arguments are separated by whitespace. An argument which is empty or contains
+/// whitespace, double quotes, backslashes or control characters must be surrounded
+/// by double quotes, inside of which `\\`, `\"`, `\n`, `\r` and `\t` are escapes.
+pub fn split_args(line: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        let quoted = match chars.peek() {
+            Some(&c) => c == '"',
+            None => break,
+        };
+        let mut arg = String::new();
+        if quoted {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => {
+                        match chars.next() {
+                            Some('\\') => arg.push('\\'),
+                            Some('"') => arg.push('"'),
+                            Some('n') => arg.push('\n'),
+                            Some('r') => arg.push('\r'),
+                            Some('t') => arg.push('\t'),
+                            _ => bail!(ErrorKind::InvalidCommand(line.to_owned())),
+                        }
+                    }
+                    Some(c) => arg.push(c),
+                    None => bail!(ErrorKind::InvalidCommand(line.to_owned())),
+                }
+            }
+            if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+                bail!(ErrorKind::InvalidCommand(line.to_owned()));
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                if c == '"' || c == '\\' {
+                    bail!(ErrorKind::InvalidCommand(line.to_owned()));
+                }
+                arg.push(c);
+                chars.next();
+            }
         }
+        args.push(arg);
+    }
+    Ok(args)
+}
+
+/// Quote an argument so it is read back as is by `split_args`
+pub fn quote_arg(arg: &str) -> String {
+    let plain = !arg.is_empty() &&
+                !arg.chars().any(|c| c.is_whitespace() || c.is_control() || c == '"' || c == '\\');
+    if plain {
+        return arg.to_owned();
+    }
+    let mut quoted = String::with_capacity(arg.len() + 2);
+    quoted.push('"');
+    for c in arg.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Protocol feature advertised during handshake
+#[derive(Clone, Debug, PartialEq)]
+pub enum Capability {
+    /// Binary length-prefixed frames instead of text lines
+    Frame,
+    /// Transfers starting at an offset
+    Resume,
+    /// SHA-256 digest appended to every transfer
+    Checksum,
+    /// File metadata with STAT
+    Stat,
+    /// Directory listing with metadata
+    ListLong,
+    /// Move files with MV
+    Rename,
+    /// Copy files with CP
+    Copy,
+    /// Challenge-response login with AUTH and PROOF
+    Scram,
+    /// Capability not known by this version of soft
+    Unknown(String),
+}
+
+impl Capability {
+    /// All capabilities supported by this version of soft
+    pub fn supported() -> Vec<Capability> {
+        vec![Capability::Frame, Capability::Resume,
+             Capability::Checksum, Capability::Stat, Capability::ListLong,
+             Capability::Rename, Capability::Copy, Capability::Scram]
+    }
+
+    /// Keep only capabilities present in both lists
+    pub fn negotiate(ours: &[Capability], theirs: &[Capability]) -> Vec<Capability> {
+        ours.iter().filter(|c| theirs.contains(c)).cloned().collect()
+    }
+}
+
+impl<'a> From<&'a str> for Capability {
+    fn from(from: &'a str) -> Capability {
+        match from {
+            "FRAME" => Capability::Frame,
+            "RESUME" => Capability::Resume,
+            "CHECKSUM" => Capability::Checksum,
+            "STAT" => Capability::Stat,
+            "LONGLIST" => Capability::ListLong,
+            "RENAME" => Capability::Rename,
+            "COPY" => Capability::Copy,
+            "SCRAM" => Capability::Scram,
+            c => Capability::Unknown(c.to_owned()),
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Capability::Frame => write!(f, "FRAME"),
+            Capability::Resume => write!(f, "RESUME"),
+            Capability::Checksum => write!(f, "CHECKSUM"),
+            Capability::Stat => write!(f, "STAT"),
+            Capability::ListLong => write!(f, "LONGLIST"),
+            Capability::Rename => write!(f, "RENAME"),
+            Capability::Copy => write!(f, "COPY"),
+            Capability::Scram => write!(f, "SCRAM"),
+            Capability::Unknown(ref c) => write!(f, "{}", c),
+        }
+    }
+}
+
+/// Operations a user is allowed to do
+///
+/// Permissions are written as letters of allowed operations: `r`ead, `w`rite, `d`elete,
+/// `m`kdir, `l`ist and `a`dmin, or `-` if none is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Permissions {
+    /// Download files and get their metadata
+    pub read: bool,
+    /// Upload, move and copy files
+    pub write: bool,
+    /// Remove files and directories
+    pub delete: bool,
+    /// Make directories
+    pub mkdir: bool,
+    /// List directories
+    pub list: bool,
+    /// Manage users of server
+    pub admin: bool,
+}
+
+impl Permissions {
+    /// Allow every operation on files, but not to manage users
+    pub fn all() -> Permissions {
+        Permissions {
+            read: true,
+            write: true,
+            delete: true,
+            mkdir: true,
+            list: true,
+            admin: false,
+        }
+    }
+
+    /// Allow nothing
+    pub fn none() -> Permissions {
+        Permissions {
+            read: false,
+            write: false,
+            delete: false,
+            mkdir: false,
+            list: false,
+            admin: false,
+        }
+    }
+
+    /// Allow reading and listing files only
+    pub fn read_only() -> Permissions {
+        Permissions {
+            read: true,
+            list: true,
+            ..Permissions::none()
+        }
+    }
+
+    /// Parse letters of allowed operations
+    pub fn try_from<S: AsRef<str>>(s: S) -> Result<Permissions> {
+        let s = s.as_ref();
+        let mut permissions = Permissions::none();
+        match s {
+            "" => bail!(ErrorKind::InvalidPermissions(s.to_owned())),
+            "-" => return Ok(permissions),
+            _ => {}
+        }
+        for c in s.chars() {
+            let allowed = match c {
+                'r' => &mut permissions.read,
+                'w' => &mut permissions.write,
+                'd' => &mut permissions.delete,
+                'm' => &mut permissions.mkdir,
+                'l' => &mut permissions.list,
+                'a' => &mut permissions.admin,
+                _ => bail!(ErrorKind::InvalidPermissions(s.to_owned())),
+            };
+            *allowed = true;
+        }
+        Ok(permissions)
+    }
+}
+
+impl Default for Permissions {
+    fn default() -> Permissions {
+        Permissions::all()
+    }
+}
+
+impl fmt::Display for Permissions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if *self == Permissions::none() {
+            return write!(f, "-");
+        }
+        let letters = [(self.read, 'r'),
+                       (self.write, 'w'),
+                       (self.delete, 'd'),
+                       (self.mkdir, 'm'),
+                       (self.list, 'l'),
+                       (self.admin, 'a')];
+        for &(allowed, letter) in &letters {
+            if allowed {
+                write!(f, "{}", letter)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Kind of a file on server
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileKind {
+    /// Regular file
+    File,
+    /// Directory
+    Dir,
+    /// Symbolic link
+    Symlink,
+    /// Anything else (socket, fifo, device...)
+    Other,
+}
+
+impl fmt::Display for FileKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FileKind::File => write!(f, "file"),
+            FileKind::Dir => write!(f, "dir"),
+            FileKind::Symlink => write!(f, "symlink"),
+            FileKind::Other => write!(f, "other"),
+        }
+    }
+}
+
+/// Metadata of a file on server
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metadata {
+    /// Kind of file
+    pub kind: FileKind,
+    /// Size in bytes
+    pub size: u64,
+    /// Last modification time in seconds since unix epoch
+    pub modified: u64,
+    /// Unix permission bits
+    pub permissions: u32,
+    /// Target of symbolic link
+    pub symlink_target: Option<String>,
+}
+
+impl Metadata {
+    /// Try converting string to metadata
+    pub fn try_from<S: AsRef<str>>(s: S) -> Result<Metadata> {
+        let s = s.as_ref().to_string();
+        let splitted = split_args(&s)?;
+        Metadata::from_args(&splitted).ok_or_else(|| ErrorKind::InvalidResponse(s).into())
+    }
+
+    /// Build metadata from splitted arguments
+    fn from_args(args: &[String]) -> Option<Metadata> {
+        if args.len() != 4 && args.len() != 5 {
+            return None;
+        }
+        let kind = match args[0].as_str() {
+            "file" => FileKind::File,
+            "dir" => FileKind::Dir,
+            "symlink" => FileKind::Symlink,
+            "other" => FileKind::Other,
+            _ => return None,
+        };
+        Some(Metadata {
+            kind,
+            size: args[1].parse().ok()?,
+            modified: args[2].parse().ok()?,
+            permissions: u32::from_str_radix(&args[3], 8).ok()?,
+            symlink_target: args.get(4).cloned(),
+        })
+    }
+}
+
+impl fmt::Display for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "{} {} {} {:o}",
+               self.kind,
+               self.size,
+               self.modified,
+               self.permissions)?;
+        if let Some(ref target) = self.symlink_target {
+            write!(f, " {}", quote_arg(target))?;
+        }
+        Ok(())
+    }
+}
+
+/// Entry of a directory listing
+#[derive(Clone, Debug, PartialEq)]
+pub struct DirEntry {
+    /// Name of entry inside its directory
+    pub name: String,
+    /// Metadata of entry
+    pub metadata: Metadata,
+}
+
+impl DirEntry {
+    /// Try converting string to directory entry
+    pub fn try_from<S: AsRef<str>>(s: S) -> Result<DirEntry> {
+        let s = s.as_ref().to_string();
+        let splitted = split_args(&s)?;
+        if splitted.is_empty() {
+            bail!(ErrorKind::InvalidResponse(s));
+        }
+        match Metadata::from_args(&splitted[1..]) {
+            Some(metadata) => {
+                Ok(DirEntry {
+                    name: splitted[0].clone(),
+                    metadata,
+                })
+            }
+            None => bail!(ErrorKind::InvalidResponse(s)),
+        }
+    }
+}
+
+impl fmt::Display for DirEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", quote_arg(&self.name), self.metadata)
     }
 }
 
 /// Status of command
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Status {
     /// Connected to server
     Connected = 1,
@@ -152,6 +698,24 @@ pub enum Status {
     NotDir = 7,
     /// Unknown path
     PathUnknown = 8,
+    /// Protocol version is not supported
+    UnsupportedVersion = 9,
+    /// Offset is past the end of file
+    InvalidOffset = 10,
+    /// Checksum of transferred data does not match
+    ChecksumMismatch = 11,
+    /// Path can't be used for this operation
+    InvalidPath = 12,
+    /// Path is outside of user root or user is not allowed to do this operation
+    PermissionDenied = 13,
+    /// User already exists
+    UserExists = 14,
+    /// User doesn't exist
+    UserUnknown = 15,
+    /// Operation is not supported by server
+    Unsupported = 16,
+    /// User name is empty or holds forbidden characters
+    InvalidUserName = 17,
     /// Unknown error
     UnkownError = 255,
 }
@@ -182,6 +746,15 @@ impl From<u8> for Status {
             6 => Status::NotFile,
             7 => Status::NotDir,
             8 => Status::PathUnknown,
+            9 => Status::UnsupportedVersion,
+            10 => Status::InvalidOffset,
+            11 => Status::ChecksumMismatch,
+            12 => Status::InvalidPath,
+            13 => Status::PermissionDenied,
+            14 => Status::UserExists,
+            15 => Status::UserUnknown,
+            16 => Status::Unsupported,
+            17 => Status::InvalidUserName,
             _ => Status::UnkownError,
         }
     }
@@ -0,0 +1,146 @@
+#![cfg(feature = "async")]
+extern crate soft_core;
+extern crate tokio;
+
+use soft_core::async_client::AsyncSoftClient;
+use soft_core::client::SoftClient;
+use soft_core::server::{AsyncSoftServer, ConnectionCounts, SoftServer, SoftServerBuilder};
+use soft_core::server::auth::Authenticator;
+use soft_core::server::storage::{MemoryStorage, Storage};
+use soft_core::types::Status;
+use std::io::{Read, Write};
+use std::net;
+use std::thread;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::{Builder, Runtime};
+
+/// Wait until no connection of server is running
+fn wait_idle<St: Storage, A: Authenticator>(server: &AsyncSoftServer<St, A>) -> ConnectionCounts {
+    for _ in 0..500 {
+        let counts = server.connection_counts();
+        if counts.active == 0 {
+            return counts;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("connections are still running");
+}
+
+#[test]
+fn async_server() {
+    let runtime = Runtime::new().unwrap();
+    let _guard = runtime.enter();
+    let server = SoftServerBuilder::new()
+        .max_threads(1)
+        .build_with(MemoryStorage::new())
+        .unwrap();
+    server.get_users().add_user("test", "test").unwrap();
+    let server = server.into_async();
+    let listener = runtime.block_on(TcpListener::bind(("127.0.0.1", soft_core::DEFAULT_PORT + 28)))
+        .unwrap();
+    let addr = listener.local_addr().unwrap();
+    runtime.spawn(server.clone().serve(listener));
+
+    // Idle connections don't hold threads, there are more than max_threads
+    let mut clients = Vec::new();
+    for _ in 0..20 {
+        let stream = runtime.block_on(TcpStream::connect(addr)).unwrap();
+        let mut client = runtime.block_on(AsyncSoftClient::connect(stream)).unwrap();
+        runtime.block_on(client.login("test", "test")).unwrap();
+        clients.push(client);
+    }
+    assert_eq!(server.active_connections(), 20);
+    runtime.block_on(clients[0].put_data(b"async data".to_vec(), "file.txt")).unwrap();
+    for client in &mut clients {
+        assert_eq!(runtime.block_on(client.get("file.txt")).unwrap(), b"async data");
+    }
+
+    // Blocking client speaks the same protocol
+    let mut client = SoftClient::connect(net::TcpStream::connect(addr).unwrap()).unwrap();
+    client.login("test", "test").unwrap();
+    assert_eq!(client.list("/").unwrap(), vec!["/file.txt".to_string()]);
+    assert!(client.get("missing.txt").is_err());
+    client.exit().unwrap();
+
+    for client in &mut clients {
+        runtime.block_on(client.exit()).unwrap();
+    }
+    assert_eq!(wait_idle(&server),
+               ConnectionCounts {
+                   active: 0,
+                   finished: 21,
+                   failed: 0,
+               });
+
+    // A client leaving without exit only ends its connection
+    let stream = runtime.block_on(TcpStream::connect(addr)).unwrap();
+    drop(stream);
+    for _ in 0..500 {
+        if server.connection_counts().finished == 22 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(wait_idle(&server).failed, 1);
+}
+
+#[test]
+fn slow_clients() {
+    let runtime = Builder::new_multi_thread().max_blocking_threads(4).enable_all().build().unwrap();
+    let _guard = runtime.enter();
+    let server = SoftServer::in_memory(None, true).into_async();
+    let listener = runtime.block_on(TcpListener::bind(("127.0.0.1", soft_core::DEFAULT_PORT + 32)))
+        .unwrap();
+    let addr = listener.local_addr().unwrap();
+    runtime.spawn(server.clone().serve(listener));
+
+    // Clients sending part of a command don't hold blocking threads
+    let mut slow = Vec::new();
+    for _ in 0..8 {
+        let mut stream = net::TcpStream::connect(addr).unwrap();
+        stream.write_all(b"PRES").unwrap();
+        slow.push(stream);
+    }
+    let stream = net::TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut client = SoftClient::connect(stream).unwrap();
+    client.login("anonymous", "").unwrap();
+    client.presence().unwrap();
+    client.exit().unwrap();
+
+    for stream in &mut slow {
+        stream.write_all(b"ENCE\n").unwrap();
+        let mut status = [0];
+        stream.read_exact(&mut status).unwrap();
+        assert_eq!(Status::from(status[0]), Status::Okay);
+    }
+}
+
+#[test]
+fn async_client() {
+    let runtime = Runtime::new().unwrap();
+    let _guard = runtime.enter();
+    let mut server = SoftServer::in_memory(None, true);
+    server.get_users().add_user("test", "test").unwrap();
+    let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 29)).unwrap();
+    let addr = server_stream.local_addr().unwrap();
+    let server_thread = thread::spawn(move || {
+        let (client, _) = server_stream.accept().unwrap();
+        server.new_connection(client);
+    });
+
+    let stream = runtime.block_on(TcpStream::connect(addr)).unwrap();
+    let mut client = runtime.block_on(AsyncSoftClient::connect(stream)).unwrap();
+    runtime.block_on(client.login("test", "test")).unwrap();
+    runtime.block_on(client.mkdir("dir")).unwrap();
+    runtime.block_on(client.cd("dir")).unwrap();
+    assert_eq!(runtime.block_on(client.cwd()).unwrap(), "/dir");
+    runtime.block_on(client.put_data(b"data".to_vec(), "file.txt")).unwrap();
+    assert_eq!(runtime.block_on(client.size("file.txt")).unwrap(), 4);
+    runtime.block_on(client.rename("file.txt", "moved.txt")).unwrap();
+    assert_eq!(runtime.block_on(client.list("/dir")).unwrap(),
+               vec!["/dir/moved.txt".to_string()]);
+    runtime.block_on(client.exit()).unwrap();
+    server_thread.join().unwrap();
+}
@@ -0,0 +1,71 @@
+extern crate soft_core;
+
+use soft_core::error::*;
+use soft_core::server::auth::*;
+use soft_core::server::users::{Password, Users};
+use std::fs;
+
+#[test]
+fn htpasswd_file() {
+    let dir = std::env::temp_dir().join("soft_test_htpasswd_file");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(".htpasswd");
+    fs::write(&path,
+              "# users\n\
+               md5:$apr1$saltsalt$LrttParrLPdxvgutaSXWJ0\n\
+               sha:{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=\n\
+               bcrypt:$2b$04$abcdefghijklmnopqrstuu2r9OfJnfCsdneAXAGHnS4UpFFP8WIrW\n\
+               crypt:secret\n")
+        .unwrap();
+    let htpasswd = HtpasswdFile::open(&path).unwrap();
+    assert!(!htpasswd.supports_scram());
+    for user in &["md5", "sha", "bcrypt"] {
+        assert_eq!(htpasswd.authenticate(user, "secret"), Some(Identity::new(user)));
+        assert_eq!(htpasswd.authenticate(user, "wrong"), None);
+    }
+    assert_eq!(htpasswd.authenticate("crypt", "secret"), None);
+    assert_eq!(htpasswd.authenticate("unknown", "secret"), None);
+    fs::write(&path, "md5:$apr1$saltsalt$LrttParrLPdxvgutaSXWJ0\n").unwrap();
+    assert_eq!(htpasswd.authenticate("sha", "secret"), None);
+    assert!(HtpasswdFile::open(dir.join("missing")).is_err());
+}
+
+#[test]
+fn static_users() {
+    let users = StaticUsers::parse("# user password [home]\n\
+                                    \n\
+                                    alice secret\n\
+                                    bob hunter2 /shared\n\
+                                    carol pass /shared rl\n")
+        .unwrap();
+    assert_eq!(users.authenticate("alice", "secret"), Some(Identity::new("alice")));
+    assert_eq!(users.authenticate("alice", "hunter2"), None);
+    let bob = users.authenticate("bob", "hunter2").unwrap();
+    assert_eq!(bob.home, Some("/shared".to_string()));
+    assert_eq!(bob.permissions, Permissions::all());
+    assert_eq!(users.identity("bob"), Some(bob));
+    assert!(users.supports_scram());
+    let keys = users.scram_keys("alice").unwrap();
+    assert!(Password::Scram(keys).verify("secret"));
+    assert_eq!(users.identity("carol").unwrap().permissions, Permissions::read_only());
+    assert_eq!(users.scram_keys("dave"), None);
+    for config in &["alice\n", "# users\nalice secret /home rx\n"] {
+        match StaticUsers::parse(config) {
+            Err(Error(ErrorKind::InvalidUserDB(line), _)) => {
+                assert_eq!(line, config.lines().count())
+            }
+            r => panic!("expected invalid user db, got {:?}", r),
+        }
+    }
+}
+
+#[test]
+fn users_authenticator() {
+    let users = Users::new();
+    users.add_user("alice", "secret").unwrap();
+    assert_eq!(users.authenticate("alice", "secret"), Some(Identity::new("alice")));
+    assert_eq!(users.authenticate("alice", "wrong"), None);
+    assert_eq!(users.identity("alice"), Some(Identity::new("alice")));
+    assert_eq!(users.identity("bob"), None);
+}
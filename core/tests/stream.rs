@@ -1,11 +1,19 @@
 extern crate soft_core;
 
 use soft_core::client::SoftClient;
-use soft_core::server::SoftServer;
+use soft_core::error::*;
+use soft_core::server::{ConnectionCounts, SoftServer, SoftServerBuilder};
+use soft_core::server::auth::{Authenticator, HtpasswdFile, Identity, Permissions, StaticUsers};
+use soft_core::server::storage::{DedupStorage, LocalStorage, MemoryStorage, PARTIAL_MAX_AGE,
+                                 Storage};
 use soft_core::types::*;
 use std::fs;
+use std::io::{self, BufRead, Read, Write};
 use std::net;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 const FILE_NAME: &'static str = "Cargo.toml";
 const FILE_DATA: &'static str = include_str!("../Cargo.toml");
@@ -19,7 +27,7 @@ fn file_stream() {
         .open(".file_stream")
         .unwrap();
     let client_stream = fs::OpenOptions::new().read(true).write(true).open(".file_stream").unwrap();
-    let mut server = SoftServer::new("test_file_stream", None, true).unwrap();
+    let mut server = SoftServer::in_memory(None, true);
     let mut client = SoftClient::new(client_stream);
     server.new_connection(server_stream);
     client.write_command(Command::Exit).unwrap();
@@ -28,7 +36,7 @@ fn file_stream() {
 
 #[test]
 fn tcp_stream() {
-    let mut server = SoftServer::new("test_tcp_stream", None, true).unwrap();
+    let mut server = SoftServer::in_memory(None, true);
     let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT)).unwrap();
     let addr = server_stream.local_addr().unwrap();
     let server_thread = thread::spawn(move || {
@@ -44,8 +52,8 @@ fn tcp_stream() {
 
 #[test]
 fn file_transfert() {
-    let mut server = SoftServer::new("test_file_transfert", None, true).unwrap();
-    server.get_users().add_user("test", "test");
+    let mut server = SoftServer::in_memory(None, true);
+    server.get_users().add_user("test", "test").unwrap();
     let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 1)).unwrap();
     let addr = server_stream.local_addr().unwrap();
     let server_thread = thread::spawn(move || {
@@ -86,8 +94,8 @@ fn file_transfert() {
 
 #[test]
 fn list_files() {
-    let mut server = SoftServer::new("test_list_files", None, true).unwrap();
-    server.get_users().add_user("test", "test");
+    let mut server = SoftServer::in_memory(None, true);
+    server.get_users().add_user("test", "test").unwrap();
     let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 3)).unwrap();
     let addr = server_stream.local_addr().unwrap();
     let server_thread = thread::spawn(move || {
@@ -102,9 +110,1057 @@ fn list_files() {
     server_thread.join().unwrap();
 }
 
+#[test]
+fn handshake() {
+    let mut server = SoftServer::in_memory(None, true);
+    let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 5)).unwrap();
+    let addr = server_stream.local_addr().unwrap();
+    let server_thread = thread::spawn(move || {
+        let (client, _) = server_stream.accept().unwrap();
+        server.new_connection(client);
+    });
+    let client_stream = net::TcpStream::connect(addr).unwrap();
+    let mut client = SoftClient::connect(client_stream).unwrap();
+    assert_eq!(client.version(), Some(soft_core::PROTOCOL_VERSION));
+    assert_eq!(client.capabilities(), Capability::supported().as_slice());
+    client.presence().unwrap();
+    client.exit().unwrap();
+    server_thread.join().unwrap();
+}
+
+#[test]
+fn frame_transfert() {
+    let mut server = SoftServer::in_memory(None, true);
+    server.get_users().add_user("test", "test").unwrap();
+    let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 6)).unwrap();
+    let addr = server_stream.local_addr().unwrap();
+    let server_thread = thread::spawn(move || {
+        let (client, _) = server_stream.accept().unwrap();
+        server.new_connection(client);
+    });
+    let client_stream = net::TcpStream::connect(addr).unwrap();
+    let mut client = SoftClient::connect(client_stream).unwrap();
+    assert!(client.has_capability(&Capability::Frame));
+    client.login("test", "test").unwrap();
+    client.put(FILE_NAME, "Cargo.toml").unwrap();
+    let data = client.get(FILE_NAME).unwrap();
+    assert_eq!(data, FILE_DATA.as_bytes());
+    assert!(client.list("/").unwrap().contains(&"/Cargo.toml".to_string()));
+    assert_eq!(client.cwd().unwrap(), "/");
+    client.exit().unwrap();
+    server_thread.join().unwrap();
+}
+
+#[test]
+fn streaming_transfert() {
+    let mut server = SoftServer::in_memory(None, true);
+    server.get_users().add_user("test", "test").unwrap();
+    let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 7)).unwrap();
+    let addr = server_stream.local_addr().unwrap();
+    let server_thread = thread::spawn(move || {
+        let (client, _) = server_stream.accept().unwrap();
+        server.new_connection(client);
+    });
+    let client_stream = net::TcpStream::connect(addr).unwrap();
+    let mut client = SoftClient::connect(client_stream).unwrap();
+    client.login("test", "test").unwrap();
+    let data = (0..1024 * 1024 + 17).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+    client.put_from(&mut io::Cursor::new(&data), data.len() as u64, "big.bin").unwrap();
+    let mut received = Vec::new();
+    let size = client.get_to("big.bin", &mut received).unwrap();
+    assert_eq!(size, data.len() as u64);
+    assert!(received == data);
+    assert!(client.get("missing.bin").is_err());
+    client.presence().unwrap();
+    client.exit().unwrap();
+    server_thread.join().unwrap();
+}
+
+#[test]
+fn resume_transfert() {
+    let mut server = SoftServer::in_memory(None, true);
+    server.get_users().add_user("test", "test").unwrap();
+    let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 8)).unwrap();
+    let addr = server_stream.local_addr().unwrap();
+    let server_thread = thread::spawn(move || {
+        for _ in 0..2 {
+            let (client, _) = server_stream.accept().unwrap();
+            server.new_connection(client);
+        }
+    });
+    let client_stream = net::TcpStream::connect(addr).unwrap();
+    let mut client = SoftClient::connect(client_stream).unwrap();
+    client.login("test", "test").unwrap();
+    let data = (0..200 * 1024).map(|i| (i % 253) as u8).collect::<Vec<u8>>();
+    let half = data.len() / 2;
+
+    // A completed upload isn't continued, even if it is shorter
+    fs::write(".resume_put", &data).unwrap();
+    client.put_from(&mut io::Cursor::new(&data[..half]), half as u64, "resume.bin").unwrap();
+    assert_eq!(client.size("resume.bin").unwrap(), half as u64);
+    assert_eq!(client.resume_put(".resume_put", "resume.bin").unwrap(),
+               data.len() as u64);
+    assert!(client.get("resume.bin").unwrap() == data);
+
+    // An interrupted upload is continued where it was cut
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    let mut status = [0];
+    stream.write_all(b"LOGIN test test\nPUT cut.bin\n").unwrap();
+    stream.read_exact(&mut status).unwrap();
+    stream.read_exact(&mut status).unwrap();
+    assert_eq!(Status::from(status[0]), Status::Okay);
+    stream.write_all(&(data.len() as u64).to_ne_bytes()).unwrap();
+    stream.write_all(&data[..half]).unwrap();
+    drop(stream);
+    for _ in 0..500 {
+        if client.resume_offset("cut.bin").unwrap() == half as u64 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(client.size("cut.bin").is_err());
+    assert_eq!(client.resume_put(".resume_put", "cut.bin").unwrap(),
+               (data.len() - half) as u64);
+    assert!(client.get("cut.bin").unwrap() == data);
+    assert_eq!(client.resume_offset("cut.bin").unwrap(), 0);
+    assert_eq!(client.list("/").unwrap().len(), 2);
+
+    fs::write(".resume_get", &data[..1000]).unwrap();
+    assert_eq!(client.resume_get("resume.bin", ".resume_get").unwrap(),
+               (data.len() - 1000) as u64);
+    assert!(fs::read(".resume_get").unwrap() == data);
+    fs::remove_file(".resume_put").unwrap();
+    fs::remove_file(".resume_get").unwrap();
+
+    assert!(client.resume_put(".missing", "resume.bin").is_err());
+    client.exit().unwrap();
+    server_thread.join().unwrap();
+}
+
+#[test]
+fn checksum_mismatch_put() {
+    let mut server = SoftServer::in_memory(None, true);
+    server.get_users().add_user("test", "test").unwrap();
+    let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 9)).unwrap();
+    let addr = server_stream.local_addr().unwrap();
+    let server_thread = thread::spawn(move || {
+        let (client, _) = server_stream.accept().unwrap();
+        server.new_connection(client);
+    });
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    let mut reader = io::BufReader::new(stream.try_clone().unwrap());
+    let mut status = [0];
+    let mut line = String::new();
+    stream.write_all(b"HELLO 1 CHECKSUM\n").unwrap();
+    reader.read_exact(&mut status).unwrap();
+    assert_eq!(Status::from(status[0]), Status::Okay);
+    reader.read_line(&mut line).unwrap();
+    stream.write_all(b"LOGIN test test\n").unwrap();
+    reader.read_exact(&mut status).unwrap();
+    assert_eq!(Status::from(status[0]), Status::Connected);
+    stream.write_all(b"PUT corrupted.bin\n").unwrap();
+    reader.read_exact(&mut status).unwrap();
+    assert_eq!(Status::from(status[0]), Status::Okay);
+    stream.write_all(&4u64.to_ne_bytes()).unwrap();
+    stream.write_all(b"data").unwrap();
+    stream.write_all(&[0; 32]).unwrap();
+    reader.read_exact(&mut status).unwrap();
+    assert_eq!(Status::from(status[0]), Status::ChecksumMismatch);
+    stream.write_all(b"EXIT\n").unwrap();
+    reader.read_exact(&mut status).unwrap();
+    assert_eq!(Status::from(status[0]), Status::Disconnected);
+    server_thread.join().unwrap();
+}
+
+#[test]
+fn checksum_mismatch_get() {
+    let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 10)).unwrap();
+    let addr = server_stream.local_addr().unwrap();
+    let server_thread = thread::spawn(move || {
+        let (mut stream, _) = server_stream.accept().unwrap();
+        let mut reader = io::BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        stream.write_all(&[Status::Okay as u8]).unwrap();
+        stream.write_all(b"HELLO 1 CHECKSUM\n").unwrap();
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "GET file.bin\n");
+        stream.write_all(&[Status::Okay as u8]).unwrap();
+        stream.write_all(&4u64.to_ne_bytes()).unwrap();
+        stream.write_all(b"data").unwrap();
+        stream.write_all(&[0; 32]).unwrap();
+    });
+    let client_stream = net::TcpStream::connect(addr).unwrap();
+    let mut client = SoftClient::new(client_stream);
+    client.hello_with(vec![Capability::Checksum]).unwrap();
+    match client.get("file.bin") {
+        Err(Error(ErrorKind::ChecksumMismatch, _)) => {}
+        r => panic!("expected checksum mismatch, got {:?}", r),
+    }
+    server_thread.join().unwrap();
+}
+
+#[test]
+fn stat_files() {
+    let mut server = SoftServer::in_memory(None, true);
+    server.get_users().add_user("test", "test").unwrap();
+    let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 11)).unwrap();
+    let addr = server_stream.local_addr().unwrap();
+    let server_thread = thread::spawn(move || {
+        let (client, _) = server_stream.accept().unwrap();
+        server.new_connection(client);
+    });
+    let client_stream = net::TcpStream::connect(addr).unwrap();
+    let mut client = SoftClient::connect(client_stream).unwrap();
+    client.login("test", "test").unwrap();
+    client.put(FILE_NAME, "Cargo.toml").unwrap();
+    let metadata = client.stat("Cargo.toml").unwrap();
+    assert_eq!(metadata.kind, FileKind::File);
+    assert_eq!(metadata.size, FILE_DATA.len() as u64);
+    assert!(metadata.modified > 0);
+    assert_eq!(metadata.symlink_target, None);
+    client.mkdir("stat_dir").unwrap();
+    assert_eq!(client.stat("stat_dir").unwrap().kind, FileKind::Dir);
+    assert!(client.stat("missing").is_err());
+    client.exit().unwrap();
+    server_thread.join().unwrap();
+}
+
+#[test]
+fn list_entries() {
+    let mut server = SoftServer::in_memory(None, true);
+    server.get_users().add_user("test", "test").unwrap();
+    let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 12)).unwrap();
+    let addr = server_stream.local_addr().unwrap();
+    let server_thread = thread::spawn(move || {
+        let (client, _) = server_stream.accept().unwrap();
+        server.new_connection(client);
+    });
+    let client_stream = net::TcpStream::connect(addr).unwrap();
+    let mut client = SoftClient::connect(client_stream).unwrap();
+    client.login("test", "test").unwrap();
+    client.mkdir("long/sub").unwrap();
+    client.put(FILE_NAME, "long/a b\nc.toml").unwrap();
+    let entries = client.list_entries("long").unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].name, "a b\nc.toml");
+    assert_eq!(entries[0].metadata.kind, FileKind::File);
+    assert_eq!(entries[0].metadata.size, FILE_DATA.len() as u64);
+    assert_eq!(entries[1].name, "sub");
+    assert_eq!(entries[1].metadata.kind, FileKind::Dir);
+    assert!(client.list_entries("missing").is_err());
+    client.exit().unwrap();
+    server_thread.join().unwrap();
+}
+
+#[test]
+fn rename_and_copy() {
+    let mut server = SoftServer::in_memory(None, true);
+    server.get_users().add_user("test", "test").unwrap();
+    let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 13)).unwrap();
+    let addr = server_stream.local_addr().unwrap();
+    let server_thread = thread::spawn(move || {
+        let (client, _) = server_stream.accept().unwrap();
+        server.new_connection(client);
+    });
+    let client_stream = net::TcpStream::connect(addr).unwrap();
+    let mut client = SoftClient::connect(client_stream).unwrap();
+    client.login("test", "test").unwrap();
+    client.mkdir("mv/dir").unwrap();
+    client.put(FILE_NAME, "mv/dir/a.toml").unwrap();
+    client.rename("mv/dir/a.toml", "mv/dir/b.toml").unwrap();
+    assert!(client.stat("mv/dir/a.toml").is_err());
+    assert_eq!(client.get("mv/dir/b.toml").unwrap(), FILE_DATA.as_bytes());
+    client.copy("mv/dir/b.toml", "mv/c.toml", false).unwrap();
+    assert_eq!(client.get("mv/c.toml").unwrap(), FILE_DATA.as_bytes());
+    assert!(client.copy("mv/dir", "mv/copy", false).is_err());
+    client.copy("mv/dir", "mv/copy", true).unwrap();
+    assert_eq!(client.get("mv/copy/b.toml").unwrap(), FILE_DATA.as_bytes());
+    assert!(client.copy("mv/dir", "mv/dir/inner", true).is_err());
+    assert!(client.rename("mv/missing", "mv/other").is_err());
+    client.exit().unwrap();
+    server_thread.join().unwrap();
+}
+
+#[test]
+fn interrupted_upload() {
+    let mut server = SoftServer::in_memory(None, true);
+    server.get_users().add_user("test", "test").unwrap();
+    let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 14)).unwrap();
+    let addr = server_stream.local_addr().unwrap();
+    let server_thread = thread::spawn(move || {
+        for _ in 0..2 {
+            let (client, _) = server_stream.accept().unwrap();
+            server.new_connection(client);
+        }
+    });
+    let client_stream = net::TcpStream::connect(addr).unwrap();
+    let mut client = SoftClient::connect(client_stream).unwrap();
+    client.login("test", "test").unwrap();
+    client.put(FILE_NAME, "atomic.toml").unwrap();
+
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    let mut status = [0];
+    stream.write_all(b"LOGIN test test\n").unwrap();
+    stream.read_exact(&mut status).unwrap();
+    stream.write_all(b"PUT atomic.toml\n").unwrap();
+    stream.read_exact(&mut status).unwrap();
+    assert_eq!(Status::from(status[0]), Status::Okay);
+    stream.write_all(&1000u64.to_ne_bytes()).unwrap();
+    stream.write_all(b"truncated").unwrap();
+    drop(stream);
+
+    assert_eq!(client.get("atomic.toml").unwrap(), FILE_DATA.as_bytes());
+    client.exit().unwrap();
+    server_thread.join().unwrap();
+}
+
+#[test]
+fn local_storage() {
+    let root = std::env::temp_dir().join("soft_test_local_storage");
+    let _ = fs::remove_dir_all(&root);
+    let storage = LocalStorage::new(root.join("tree")).unwrap();
+    let mut server = SoftServerBuilder::new()
+        .user_db(root.join("db/users.db"))
+        .build_with(storage)
+        .unwrap();
+    server.get_users().add_user("test", "test").unwrap();
+    let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 15)).unwrap();
+    let addr = server_stream.local_addr().unwrap();
+    let server_thread = thread::spawn(move || {
+        let (client, _) = server_stream.accept().unwrap();
+        server.new_connection(client);
+    });
+    let client_stream = net::TcpStream::connect(addr).unwrap();
+    let mut client = SoftClient::connect(client_stream).unwrap();
+    client.login("test", "test").unwrap();
+    client.mkdir("dir").unwrap();
+    client.put(FILE_NAME, "dir/local.toml").unwrap();
+    let data = fs::read(root.join("tree/users/test/dir/local.toml")).unwrap();
+    assert_eq!(data, FILE_DATA.as_bytes());
+    let old = fs::File::create(root.join("tree/users/test/dir/old.toml")).unwrap();
+    old.set_modified(UNIX_EPOCH - Duration::from_secs(86400)).unwrap();
+    drop(old);
+    let entries = client.list_entries("dir").unwrap();
+    assert_eq!(entries.iter().find(|e| e.name == "old.toml").unwrap().metadata.modified, 0);
+    client.cd("dir").unwrap();
+    client.rm("local.toml").unwrap();
+    assert!(!root.join("tree/users/test/dir/local.toml").exists());
+    client.exit().unwrap();
+    server_thread.join().unwrap();
+}
+
+#[test]
+fn stale_uploads() {
+    let root = std::env::temp_dir().join("soft_test_stale_uploads");
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("dir")).unwrap();
+    // Process 1 runs as long as the system does
+    let running = ".soft-upload.running.1.0";
+    let dead = format!(".soft-upload.dead.{}.0", u32::MAX);
+    fs::write(root.join("dir").join(running), FILE_DATA).unwrap();
+    fs::write(root.join("dir").join(&dead), FILE_DATA).unwrap();
+    fs::write(root.join(".soft-upload.kept.partial"), FILE_DATA).unwrap();
+    let expired = fs::File::create(root.join(".soft-upload.expired.partial")).unwrap();
+    expired.set_modified(SystemTime::now() - PARTIAL_MAX_AGE - Duration::from_secs(60)).unwrap();
+    drop(expired);
+    LocalStorage::new(&root).unwrap();
+    assert!(root.join("dir").join(running).exists());
+    assert!(!root.join("dir").join(&dead).exists());
+    assert!(root.join(".soft-upload.kept.partial").exists());
+    assert!(!root.join(".soft-upload.expired.partial").exists());
+}
+
+#[test]
+fn memory_storage() {
+    let mut server = SoftServer::in_memory(None, true);
+    server.get_users().add_user("test", "test").unwrap();
+    let storage = server.get_storage();
+    let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 16)).unwrap();
+    let addr = server_stream.local_addr().unwrap();
+    let server_thread = thread::spawn(move || {
+        let (client, _) = server_stream.accept().unwrap();
+        server.new_connection(client);
+    });
+    let client_stream = net::TcpStream::connect(addr).unwrap();
+    let mut client = SoftClient::connect(client_stream).unwrap();
+    client.login("test", "test").unwrap();
+    client.mkdir("mem/sub").unwrap();
+    client.put(FILE_NAME, "mem/sub/a.toml").unwrap();
+    assert_eq!(client.list("mem").unwrap(), vec!["/mem/sub/".to_string()]);
+    assert!(client.rm("mem/sub").is_err());
+    assert!(client.rmdir("mem/sub/a.toml", false).is_err());
+    assert!(client.rmdir("mem", false).is_err());
+    assert_eq!(client.size("mem/sub/a.toml").unwrap(), FILE_DATA.len() as u64);
+    client.copy("mem/sub", "mem/copy", true).unwrap();
+    client.rm("mem/sub/a.toml").unwrap();
+    client.rmdir("mem/sub", false).unwrap();
+    client.exit().unwrap();
+    server_thread.join().unwrap();
+
+    let mut data = Vec::new();
+    storage.open("/users/test/mem/copy/a.toml", 0).unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data, FILE_DATA.as_bytes());
+    assert!(storage.stat("/users/test/mem/sub").is_err());
+}
+
+#[test]
+fn dedup_storage() {
+    let root = std::env::temp_dir().join("soft_test_dedup_storage");
+    let _ = fs::remove_dir_all(&root);
+    let storage = DedupStorage::new(&root).unwrap();
+    let mut server = SoftServerBuilder::new()
+        .user_db(root.join("db/users.db"))
+        .build_with(storage)
+        .unwrap();
+    for user in &["alice", "bob"] {
+        server.get_users().add_user(user, user).unwrap();
+    }
+    let storage = server.get_storage();
+    let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 17)).unwrap();
+    let addr = server_stream.local_addr().unwrap();
+    let server_thread = thread::spawn(move || {
+        for _ in 0..2 {
+            let (client, _) = server_stream.accept().unwrap();
+            server.new_connection(client);
+        }
+    });
+    let mut alice = SoftClient::connect(net::TcpStream::connect(addr).unwrap()).unwrap();
+    let mut bob = SoftClient::connect(net::TcpStream::connect(addr).unwrap()).unwrap();
+    alice.login("alice", "alice").unwrap();
+    bob.login("bob", "bob").unwrap();
+    alice.put(FILE_NAME, "a.toml").unwrap();
+    bob.mkdir("dir").unwrap();
+    bob.put(FILE_NAME, "dir/b.toml").unwrap();
+    bob.copy("dir/b.toml", "dir/c.toml", false).unwrap();
+    assert_eq!(count_blobs(&root.join("blobs")), 1);
+    assert_eq!(bob.get("dir/c.toml").unwrap(), FILE_DATA.as_bytes());
+    assert_eq!(bob.size("dir/c.toml").unwrap(), FILE_DATA.len() as u64);
+    alice.rm("a.toml").unwrap();
+    assert_eq!(count_blobs(&root.join("blobs")), 1);
+    bob.rmdir("dir", true).unwrap();
+    assert_eq!(count_blobs(&root.join("blobs")), 0);
+
+    alice.put(FILE_NAME, "a.toml").unwrap();
+    fs::remove_file(root.join("tree/users/alice/a.toml")).unwrap();
+    let stats = storage.gc().unwrap();
+    assert_eq!(stats.removed, 1);
+    assert_eq!(stats.freed, FILE_DATA.len() as u64);
+    assert_eq!(count_blobs(&root.join("blobs")), 0);
+
+    // A failed copy keeps no reference to the blob
+    alice.put(FILE_NAME, "a.toml").unwrap();
+    alice.mkdir("dir").unwrap();
+    assert!(storage.copy("/users/alice/a.toml", "/users/alice/dir").is_err());
+    alice.rm("a.toml").unwrap();
+    assert_eq!(count_blobs(&root.join("blobs")), 0);
+    alice.exit().unwrap();
+    bob.exit().unwrap();
+    server_thread.join().unwrap();
+}
+
+#[test]
+fn dedup_checksum_mismatch() {
+    let root = std::env::temp_dir().join("soft_test_dedup_checksum");
+    let _ = fs::remove_dir_all(&root);
+    let mut server = SoftServerBuilder::new().build_with(DedupStorage::new(&root).unwrap()).unwrap();
+    server.get_users().add_user("test", "test").unwrap();
+    let storage = server.get_storage();
+    let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 31)).unwrap();
+    let addr = server_stream.local_addr().unwrap();
+    let server_thread = thread::spawn(move || {
+        let (client, _) = server_stream.accept().unwrap();
+        server.new_connection(client);
+    });
+    let mut stream = net::TcpStream::connect(addr).unwrap();
+    let mut reader = io::BufReader::new(stream.try_clone().unwrap());
+    let mut status = [0];
+    let mut line = String::new();
+    stream.write_all(b"HELLO 1 CHECKSUM\n").unwrap();
+    reader.read_exact(&mut status).unwrap();
+    reader.read_line(&mut line).unwrap();
+    stream.write_all(b"LOGIN test test\n").unwrap();
+    reader.read_exact(&mut status).unwrap();
+    assert_eq!(Status::from(status[0]), Status::Connected);
+    stream.write_all(b"PUT corrupted.bin\n").unwrap();
+    reader.read_exact(&mut status).unwrap();
+    assert_eq!(Status::from(status[0]), Status::Okay);
+    stream.write_all(&4u64.to_ne_bytes()).unwrap();
+    stream.write_all(b"data").unwrap();
+    stream.write_all(&[0; 32]).unwrap();
+    reader.read_exact(&mut status).unwrap();
+    assert_eq!(Status::from(status[0]), Status::ChecksumMismatch);
+    stream.write_all(b"EXIT\n").unwrap();
+    reader.read_exact(&mut status).unwrap();
+    assert_eq!(Status::from(status[0]), Status::Disconnected);
+    server_thread.join().unwrap();
+
+    assert!(storage.stat("/users/test/corrupted.bin").is_err());
+    assert_eq!(storage.list("/users/test").unwrap().len(), 0);
+    assert_eq!(count_blobs(&root.join("blobs")), 0);
+}
+
+/// Count blobs of a deduplicating storage
+fn count_blobs(dir: &Path) -> usize {
+    let mut count = 0;
+    for entry in fs::read_dir(dir).unwrap() {
+        for blob in fs::read_dir(entry.unwrap().path()).unwrap() {
+            if blob.unwrap().path().extension().is_none() {
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+#[test]
+fn server_builder() {
+    let root = std::env::temp_dir().join("soft_test_server_builder");
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("tree/docs")).unwrap();
+    fs::write(root.join("tree/docs/existing.toml"), FILE_DATA).unwrap();
+    let mut server = SoftServerBuilder::new()
+        .root(root.join("tree"))
+        .user_db(root.join("db/users.db"))
+        .home("/")
+        .build()
+        .unwrap();
+    server.get_users().add_user("test", "test").unwrap();
+    let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 18)).unwrap();
+    let addr = server_stream.local_addr().unwrap();
+    let server_thread = thread::spawn(move || {
+        let (client, _) = server_stream.accept().unwrap();
+        server.new_connection(client);
+    });
+    let client_stream = net::TcpStream::connect(addr).unwrap();
+    let mut client = SoftClient::connect(client_stream).unwrap();
+    assert!(client.login("anonymous", "").is_err());
+    client.login("test", "test").unwrap();
+    assert_eq!(client.get("docs/existing.toml").unwrap(), FILE_DATA.as_bytes());
+    client.put(FILE_NAME, "/new.toml").unwrap();
+    assert!(root.join("tree/new.toml").exists());
+    client.exit().unwrap();
+    server_thread.join().unwrap();
+}
+
+#[test]
+fn path_traversal() {
+    let mut server = SoftServer::in_memory(None, false);
+    server.get_users().add_user("alice", "alice").unwrap();
+    server.get_users().add_user("bob", "bob").unwrap();
+    let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 19)).unwrap();
+    let addr = server_stream.local_addr().unwrap();
+    let server_thread = thread::spawn(move || {
+        for _ in 0..2 {
+            let (client, _) = server_stream.accept().unwrap();
+            server.new_connection(client);
+        }
+    });
+    let mut bob = SoftClient::connect(net::TcpStream::connect(addr).unwrap()).unwrap();
+    bob.login("bob", "bob").unwrap();
+    bob.put(FILE_NAME, "secret.toml").unwrap();
+    let mut alice = SoftClient::connect(net::TcpStream::connect(addr).unwrap()).unwrap();
+    alice.login("alice", "alice").unwrap();
+    alice.mkdir("sub").unwrap();
+    alice.put(FILE_NAME, "a.toml").unwrap();
+
+    assert_denied(alice.rm("../bob/secret.toml"));
+    assert_denied(alice.rm("/../bob/secret.toml"));
+    assert_denied(alice.rm("sub/../../bob/secret.toml"));
+    assert_denied(alice.rmdir("../bob", true));
+    assert_denied(alice.get("../bob/secret.toml"));
+    assert_denied(alice.get("../../../../etc/passwd"));
+    assert_denied(alice.size("/sub/../../bob/secret.toml"));
+    assert_denied(alice.stat("../bob"));
+    assert_denied(alice.list(".."));
+    assert_denied(alice.list_entries("../bob"));
+    assert_denied(alice.mkdir("../evil"));
+    assert_denied(alice.put(FILE_NAME, "../bob/evil.toml"));
+    assert_denied(alice.rename("a.toml", "../bob/a.toml"));
+    assert_denied(alice.rename("../bob/secret.toml", "stolen.toml"));
+    assert_denied(alice.copy("../bob/secret.toml", "stolen.toml", false));
+    assert_denied(alice.cd(".."));
+    alice.cd("sub").unwrap();
+    assert_denied(alice.cd("../.."));
+    assert_denied(alice.get("../../bob/secret.toml"));
+    assert_eq!(alice.get("../a.toml").unwrap(), FILE_DATA.as_bytes());
+    assert_eq!(alice.get("/sub/./../a.toml").unwrap(), FILE_DATA.as_bytes());
+    alice.cd("/").unwrap();
+    assert_eq!(alice.list("/").unwrap().len(), 2);
+
+    assert_eq!(bob.get("secret.toml").unwrap(), FILE_DATA.as_bytes());
+    assert_eq!(bob.list("/").unwrap(), vec!["/secret.toml".to_string()]);
+    alice.exit().unwrap();
+    bob.exit().unwrap();
+    server_thread.join().unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn symlink_escape() {
+    use std::os::unix::fs::symlink;
+
+    let root = std::env::temp_dir().join("soft_test_symlink_escape");
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("data/users/alice/sub")).unwrap();
+    fs::create_dir_all(root.join("data/users/bob")).unwrap();
+    fs::create_dir_all(root.join("outside")).unwrap();
+    fs::write(root.join("data/users/bob/secret.toml"), FILE_DATA).unwrap();
+    fs::write(root.join("outside/secret.toml"), FILE_DATA).unwrap();
+    fs::write(root.join("data/users/alice/a.toml"), FILE_DATA).unwrap();
+    let alice_home = root.join("data/users/alice");
+    symlink(root.join("data/users/bob"), alice_home.join("bob_link")).unwrap();
+    symlink(root.join("outside"), alice_home.join("outside_link")).unwrap();
+    symlink(root.join("outside/missing"), alice_home.join("dangling")).unwrap();
+    symlink(alice_home.join("a.toml"), alice_home.join("sub/inner_link")).unwrap();
+    fs::create_dir_all(alice_home.join("nested/deep")).unwrap();
+    symlink(root.join("outside"), alice_home.join("nested/deep/escape")).unwrap();
+    fs::create_dir_all(alice_home.join("looping")).unwrap();
+    symlink(alice_home.join("looping"), alice_home.join("looping/self")).unwrap();
+    let mut server = SoftServerBuilder::new().root(root.join("data")).build().unwrap();
+    server.get_users().add_user("alice", "alice").unwrap();
+    let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 20)).unwrap();
+    let addr = server_stream.local_addr().unwrap();
+    let server_thread = thread::spawn(move || {
+        let (client, _) = server_stream.accept().unwrap();
+        server.new_connection(client);
+    });
+    let mut client = SoftClient::connect(net::TcpStream::connect(addr).unwrap()).unwrap();
+    client.login("alice", "alice").unwrap();
+
+    assert_denied(client.get("bob_link/secret.toml"));
+    assert_denied(client.list("bob_link"));
+    assert_denied(client.rm("bob_link/secret.toml"));
+    assert_denied(client.get("outside_link/secret.toml"));
+    assert_denied(client.list_entries("outside_link"));
+    assert_denied(client.put(FILE_NAME, "outside_link/evil.toml"));
+    assert_denied(client.mkdir("outside_link/evil"));
+    assert_denied(client.cd("outside_link"));
+    assert_denied(client.put(FILE_NAME, "dangling"));
+    assert_denied(client.copy("bob_link", "copy", true));
+    assert_denied(client.copy("nested", "nested_copy", true));
+    assert_denied(client.copy("looping", "looping_copy", true));
+    client.copy("sub", "sub_copy", true).unwrap();
+    assert_eq!(client.get("sub_copy/inner_link").unwrap(), FILE_DATA.as_bytes());
+    assert_eq!(client.get("sub/inner_link").unwrap(), FILE_DATA.as_bytes());
+    assert_eq!(client.stat("outside_link").unwrap().kind, FileKind::Symlink);
+    client.rm("outside_link").unwrap();
+
+    assert!(root.join("data/users/bob/secret.toml").exists());
+    assert!(root.join("outside/secret.toml").exists());
+    assert!(!root.join("outside/missing").exists());
+    assert!(!root.join("outside/evil.toml").exists());
+    assert!(!alice_home.join("nested_copy").exists());
+    client.exit().unwrap();
+    server_thread.join().unwrap();
+}
+
+#[test]
+fn scram_login() {
+    let mut server = SoftServer::in_memory(None, true);
+    server.get_users().add_user("test", "secret pass").unwrap();
+    let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 21)).unwrap();
+    let addr = server_stream.local_addr().unwrap();
+    let server_thread = thread::spawn(move || {
+        for _ in 0..3 {
+            let (client, _) = server_stream.accept().unwrap();
+            server.new_connection(client);
+        }
+    });
+
+    let written = Arc::new(Mutex::new(Vec::new()));
+    let client_stream = Recorder(net::TcpStream::connect(addr).unwrap(), written.clone());
+    let mut client = SoftClient::connect(client_stream).unwrap();
+    assert!(client.has_capability(&Capability::Scram));
+    client.login("test", "secret pass").unwrap();
+    client.mkdir("dir").unwrap();
+    client.exit().unwrap();
+    let written = String::from_utf8_lossy(&written.lock().unwrap()).into_owned();
+    assert!(written.contains("AUTH test "));
+    assert!(!written.contains("secret"));
+
+    let client_stream = net::TcpStream::connect(addr).unwrap();
+    let mut client = SoftClient::connect(client_stream).unwrap();
+    match client.login("test", "wrong") {
+        Err(Error(ErrorKind::InvalidLogin, _)) => {}
+        r => panic!("expected invalid login, got {:?}", r),
+    }
+    match client.login("unknown", "secret pass") {
+        Err(Error(ErrorKind::InvalidLogin, _)) => {}
+        r => panic!("expected invalid login, got {:?}", r),
+    }
+    client.exit().unwrap();
+
+    let client_stream = net::TcpStream::connect(addr).unwrap();
+    let mut client = SoftClient::new(client_stream);
+    client.hello_with(vec![Capability::Checksum]).unwrap();
+    assert!(!client.has_capability(&Capability::Scram));
+    client.login("test", "secret pass").unwrap();
+    assert_eq!(client.list("/").unwrap(), vec!["/dir/".to_string()]);
+    client.exit().unwrap();
+    server_thread.join().unwrap();
+}
+
+#[test]
+fn scram_salts() {
+    let users = StaticUsers::parse("alice secret\n").unwrap();
+    let mut server = SoftServerBuilder::new().build_from(MemoryStorage::new(), users);
+    let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 33)).unwrap();
+    let addr = server_stream.local_addr().unwrap();
+    let server_thread = thread::spawn(move || {
+        let (client, _) = server_stream.accept().unwrap();
+        server.new_connection(client);
+    });
+    let mut client = SoftClient::connect(net::TcpStream::connect(addr).unwrap()).unwrap();
+    let mut salt = |user: &str| {
+        client.write_command(Command::Auth(user.to_string(), "nonce".to_string())).unwrap();
+        assert_eq!(client.read_status().unwrap(), Status::Okay);
+        client.read_line().unwrap().split(' ').nth(1).unwrap().to_string()
+    };
+
+    // Salts of unknown users don't change either, they can't be told apart from known ones
+    let alice = salt("alice");
+    assert_eq!(salt("alice"), alice);
+    let nobody = salt("nobody");
+    assert_eq!(salt("nobody"), nobody);
+    assert_eq!(nobody.len(), alice.len());
+    assert!(salt("other") != nobody);
+    client.exit().unwrap();
+    server_thread.join().unwrap();
+}
+
+/// Stream keeping a copy of everything written to it
+struct Recorder(net::TcpStream, Arc<Mutex<Vec<u8>>>);
+
+impl Read for Recorder {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for Recorder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.0.write(buf)?;
+        self.1.lock().unwrap().extend_from_slice(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[test]
+fn static_users_login() {
+    let users = StaticUsers::parse("alice secret\nbob hunter2 /shared\n").unwrap();
+    let mut server = SoftServerBuilder::new().build_from(MemoryStorage::new(), users);
+    let storage = server.get_storage();
+    let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 24)).unwrap();
+    let addr = server_stream.local_addr().unwrap();
+    let server_thread = thread::spawn(move || {
+        let (client, _) = server_stream.accept().unwrap();
+        server.new_connection(client);
+    });
+    let client_stream = net::TcpStream::connect(addr).unwrap();
+    let mut client = SoftClient::connect(client_stream).unwrap();
+    assert!(client.has_capability(&Capability::Scram));
+    assert!(client.login("alice", "hunter2").is_err());
+    client.login("bob", "hunter2").unwrap();
+    client.mkdir("dir").unwrap();
+    client.exit().unwrap();
+    server_thread.join().unwrap();
+    assert!(storage.stat("/shared/dir").is_ok());
+}
+
+#[test]
+fn htpasswd_login() {
+    let dir = std::env::temp_dir().join("soft_test_htpasswd_login");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(".htpasswd");
+    fs::write(&path, "test:{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=\n").unwrap();
+    let htpasswd = HtpasswdFile::open(&path).unwrap();
+    let mut server = SoftServerBuilder::new().build_from(MemoryStorage::new(), htpasswd);
+    let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 25)).unwrap();
+    let addr = server_stream.local_addr().unwrap();
+    let server_thread = thread::spawn(move || {
+        let (client, _) = server_stream.accept().unwrap();
+        server.new_connection(client);
+    });
+    let client_stream = net::TcpStream::connect(addr).unwrap();
+    let mut client = SoftClient::connect(client_stream).unwrap();
+    assert!(!client.has_capability(&Capability::Scram));
+    assert!(client.login("test", "wrong").is_err());
+    client.login("test", "secret").unwrap();
+    assert_eq!(client.cwd().unwrap(), "/");
+    client.exit().unwrap();
+    server_thread.join().unwrap();
+}
+
+#[test]
+fn user_permissions() {
+    let mut server = SoftServerBuilder::new()
+        .home("/shared")
+        .allow_anonymous(true)
+        .build_with(MemoryStorage::new())
+        .unwrap();
+    let users = server.get_users();
+    users.add_user("writer", "writer").unwrap();
+    users.add_user_with("reader", "reader", Permissions::read_only()).unwrap();
+    let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 26)).unwrap();
+    let addr = server_stream.local_addr().unwrap();
+    let server_thread = thread::spawn(move || {
+        for _ in 0..3 {
+            let (client, _) = server_stream.accept().unwrap();
+            server.new_connection(client);
+        }
+    });
+    let client_stream = net::TcpStream::connect(addr).unwrap();
+    let mut client = SoftClient::connect(client_stream).unwrap();
+    client.login("writer", "writer").unwrap();
+    client.mkdir("dir").unwrap();
+    client.put(FILE_NAME, "dir/file.toml").unwrap();
+    client.exit().unwrap();
+
+    for &(user, pass) in &[("reader", "reader"), ("anonymous", "")] {
+        let client_stream = net::TcpStream::connect(addr).unwrap();
+        let mut client = SoftClient::connect(client_stream).unwrap();
+        client.login(user, pass).unwrap();
+        assert_eq!(client.list("/dir").unwrap(), vec!["/dir/file.toml".to_string()]);
+        assert_eq!(client.get("dir/file.toml").unwrap(), FILE_DATA.as_bytes());
+        assert_denied(client.put(FILE_NAME, "dir/file.toml"));
+        assert_denied(client.mkdir("other"));
+        assert_denied(client.rm("dir/file.toml"));
+        assert_denied(client.rmdir("dir", true));
+        assert_denied(client.rename("dir/file.toml", "file.toml"));
+        assert_denied(client.copy("dir/file.toml", "file.toml", false));
+        client.exit().unwrap();
+    }
+    server_thread.join().unwrap();
+}
+
+#[test]
+fn user_admin() {
+    let mut server = SoftServerBuilder::new()
+        .allow_anonymous(true)
+        .build_with(MemoryStorage::new())
+        .unwrap();
+    let users = server.get_users();
+    users.add_user_with("admin", "admin", Permissions { admin: true, ..Permissions::all() })
+        .unwrap();
+    let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 23)).unwrap();
+    let addr = server_stream.local_addr().unwrap();
+    let server_thread = thread::spawn(move || {
+        for _ in 0..5 {
+            let (client, _) = server_stream.accept().unwrap();
+            server.new_connection(client);
+        }
+    });
+    let connect = || SoftClient::connect(net::TcpStream::connect(addr).unwrap()).unwrap();
+
+    let mut client = connect();
+    client.login("admin", "admin").unwrap();
+    client.add_user("bob", "bob", Permissions::read_only()).unwrap();
+    client.add_user("carol", "carol", Permissions::all()).unwrap();
+    match client.add_user("bob", "other", Permissions::all()) {
+        Err(Error(ErrorKind::UserExists(ref user), _)) if user == "bob" => {}
+        r => panic!("expected existing user, got {:?}", r),
+    }
+    match client.add_user("a b", "other", Permissions::all()) {
+        Err(Error(ErrorKind::InvalidUserName(ref user), _)) if user == "a b" => {}
+        r => panic!("expected invalid user name, got {:?}", r),
+    }
+    match client.remove_user("nobody") {
+        Err(Error(ErrorKind::UserUnknown(ref user), _)) if user == "nobody" => {}
+        r => panic!("expected unknown user, got {:?}", r),
+    }
+    client.set_password("carol", "changed").unwrap();
+    assert_eq!(client.users().unwrap(),
+               vec![("admin".to_string(), Permissions { admin: true, ..Permissions::all() }),
+                    ("bob".to_string(), Permissions::read_only()),
+                    ("carol".to_string(), Permissions::all())]);
+    client.exit().unwrap();
+
+    let mut client = connect();
+    client.login("bob", "bob").unwrap();
+    assert_denied(client.users());
+    assert_denied(client.add_user("dave", "dave", Permissions::all()));
+    assert_denied(client.remove_user("carol"));
+    assert_denied(client.set_password("carol", "bob"));
+    client.passwd("new").unwrap();
+    client.set_password("bob", "newer").unwrap();
+    client.exit().unwrap();
+
+    let mut client = connect();
+    assert!(client.login("carol", "carol").is_err());
+    client.login("carol", "changed").unwrap();
+    client.exit().unwrap();
+
+    let mut client = connect();
+    client.login("anonymous", "").unwrap();
+    assert_denied(client.passwd("anonymous"));
+    assert_denied(client.set_password("anonymous", "anonymous"));
+    client.exit().unwrap();
+
+    let mut client = connect();
+    client.login("bob", "newer").unwrap();
+    client.exit().unwrap();
+    server_thread.join().unwrap();
+}
+
+/// Authenticator whose handler panics for user `panic`
+struct PanickingAuthenticator;
+
+impl Authenticator for PanickingAuthenticator {
+    fn authenticate(&self, user: &str, _pass: &str) -> Option<Identity> {
+        if user == "panic" {
+            panic!("authenticator failure");
+        }
+        Some(Identity::new(user))
+    }
+}
+
+/// Wait until no connection of server is running
+fn wait_idle<St: Storage, A: Authenticator>(server: &SoftServer<St, A>) -> ConnectionCounts {
+    for _ in 0..500 {
+        let counts = server.connection_counts();
+        if counts.active == 0 {
+            return counts;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("connections are still running");
+}
+
+#[test]
+fn connection_pool() {
+    let mut server = SoftServerBuilder::new()
+        .max_threads(1)
+        .build_from(MemoryStorage::new(), PanickingAuthenticator);
+    assert_eq!(server.max_threads(), 1);
+    let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 27)).unwrap();
+    let addr = server_stream.local_addr().unwrap();
+    let connect = || {
+        let client = net::TcpStream::connect(addr).unwrap();
+        (client, server_stream.accept().unwrap().0)
+    };
+
+    let (client, stream) = connect();
+    server.new_connection(stream);
+    let mut client = SoftClient::connect(client).unwrap();
+    client.login("alice", "alice").unwrap();
+    assert_eq!(server.active_connections(), 1);
+
+    // Only thread is busy, next connection waits until it is free
+    let (waiting, stream) = connect();
+    let pending = thread::spawn(move || {
+        server.new_connection(stream);
+        server
+    });
+    thread::sleep(Duration::from_millis(100));
+    client.exit().unwrap();
+    let exited = Instant::now();
+    let mut server = pending.join().unwrap();
+    assert!(exited.elapsed() < Duration::from_secs(2));
+    let mut client = SoftClient::connect(waiting).unwrap();
+    client.presence().unwrap();
+    assert_eq!(server.active_connections(), 1);
+    client.exit().unwrap();
+    assert_eq!(wait_idle(&server),
+               ConnectionCounts {
+                   active: 0,
+                   finished: 2,
+                   failed: 0,
+               });
+
+    // A panic or an error of a connection only frees its thread
+    let (client, stream) = connect();
+    server.new_connection(stream);
+    let mut client = SoftClient::connect(client).unwrap();
+    assert!(client.login("panic", "panic").is_err());
+    let (client, stream) = connect();
+    server.new_connection(stream);
+    drop(client);
+    let (client, stream) = connect();
+    server.new_connection(stream);
+    let mut client = SoftClient::connect(client).unwrap();
+    client.login("bob", "bob").unwrap();
+    client.exit().unwrap();
+    assert_eq!(wait_idle(&server),
+               ConnectionCounts {
+                   active: 0,
+                   finished: 5,
+                   failed: 2,
+               });
+}
+
+#[test]
+fn graceful_shutdown() {
+    let mut server = SoftServer::in_memory(None, true);
+    server.get_users().add_user("test", "test").unwrap();
+    let storage = server.get_storage();
+    let shutdown = server.shutdown_handle();
+    let listener = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 30)).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_thread = thread::spawn(move || {
+        server.serve(listener).unwrap();
+        server
+    });
+    let upload = |path: &str| {
+        let mut stream = net::TcpStream::connect(addr).unwrap();
+        let mut status = [0];
+        stream.write_all(b"LOGIN test test\n").unwrap();
+        stream.read_exact(&mut status).unwrap();
+        stream.write_all(format!("PUT {}\n", path).as_bytes()).unwrap();
+        stream.read_exact(&mut status).unwrap();
+        assert_eq!(Status::from(status[0]), Status::Okay);
+        stream
+    };
+
+    let mut idle = SoftClient::connect(net::TcpStream::connect(addr).unwrap()).unwrap();
+    idle.login("test", "test").unwrap();
+    let mut finishing = upload("finished.toml");
+    let mut stalled = upload("stalled.toml");
+    let requested = Instant::now();
+    shutdown.shutdown(Duration::from_secs(1));
+    assert!(shutdown.is_requested());
+
+    // Running transfers may end, then their client is told the server left
+    thread::sleep(Duration::from_millis(200));
+    finishing.write_all(&(FILE_DATA.len() as u64).to_ne_bytes()).unwrap();
+    finishing.write_all(FILE_DATA.as_bytes()).unwrap();
+    let mut status = [0];
+    finishing.read_exact(&mut status).unwrap();
+    assert_eq!(Status::from(status[0]), Status::Disconnected);
+    match idle.presence() {
+        Err(Error(ErrorKind::Disconnected, _)) => {}
+        r => panic!("expected disconnection, got {:?}", r),
+    }
+
+    // Transfers still running after grace are cut
+    let server = server_thread.join().unwrap();
+    assert!(requested.elapsed() >= Duration::from_secs(1));
+    assert!(requested.elapsed() < Duration::from_secs(5));
+    assert_eq!(stalled.read(&mut status).unwrap_or(0), 0);
+    assert_eq!(storage.stat("/users/test/finished.toml").unwrap().size,
+               FILE_DATA.len() as u64);
+    assert!(storage.stat("/users/test/stalled.toml").is_err());
+    assert_eq!(server.active_connections(), 0);
+    assert!(net::TcpStream::connect(addr).is_err());
+}
+
+/// Check that a request was refused for leaving user home
+fn assert_denied<T: std::fmt::Debug>(result: Result<T>) {
+    match result {
+        Err(Error(ErrorKind::PermissionDenied, _)) => {}
+        r => panic!("expected permission denied, got {:?}", r),
+    }
+}
+
 #[test]
 fn drop_exit() {
-    let mut server = SoftServer::new("test_drop_exit", None, true).unwrap();
+    let mut server = SoftServer::in_memory(None, true);
     let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 4)).unwrap();
     let addr = server_stream.local_addr().unwrap();
     let server_thread = thread::spawn(move || {
@@ -0,0 +1,76 @@
+#![cfg(feature = "tls")]
+extern crate rcgen;
+extern crate soft_core;
+
+use soft_core::client::SoftClient;
+use soft_core::error::*;
+use soft_core::server::SoftServer;
+use soft_core::tls;
+use std::fs;
+use std::net;
+use std::path::PathBuf;
+use std::thread;
+
+/// Write a self-signed certificate for localhost and its key, returning their paths
+fn self_signed(dir: &str) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(dir);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+    let certified = rcgen::generate_simple_self_signed(names).unwrap();
+    let cert = dir.join("cert.pem");
+    let key = dir.join("key.pem");
+    fs::write(&cert, certified.cert.pem()).unwrap();
+    fs::write(&key, certified.signing_key.serialize_pem()).unwrap();
+    (cert, key)
+}
+
+#[test]
+fn tls_transfert() {
+    let (cert, key) = self_signed("soft_test_tls_transfert");
+    let (other_cert, _) = self_signed("soft_test_tls_transfert_other");
+    let config = tls::server_config_from_files(&cert, &key).unwrap();
+    let mut server = SoftServer::in_memory(None, true);
+    server.get_users().add_user("test", "test").unwrap();
+    let server_stream = net::TcpListener::bind(("0.0.0.0", soft_core::DEFAULT_PORT + 22)).unwrap();
+    let addr = server_stream.local_addr().unwrap();
+    let server_thread = thread::spawn(move || {
+        for _ in 0..3 {
+            let (client, _) = server_stream.accept().unwrap();
+            server.new_connection(tls::accept(&config, client).unwrap());
+        }
+    });
+
+    let config = tls::client_config_from_file(Some(&cert)).unwrap();
+    let stream = net::TcpStream::connect(addr).unwrap();
+    let stream = tls::connect(&config, "localhost", stream).unwrap();
+    let mut client = SoftClient::connect(stream).unwrap();
+    client.login("test", "test").unwrap();
+    client.put_from(&mut &b"over tls"[..], 8, "secret.txt").unwrap();
+    assert_eq!(client.get("secret.txt").unwrap(), b"over tls");
+    client.exit().unwrap();
+
+    let stream = net::TcpStream::connect(addr).unwrap();
+    let stream = tls::connect(&config, "soft.invalid", stream).unwrap();
+    assert!(SoftClient::connect(stream).is_err());
+
+    let config = tls::client_config_from_file(Some(&other_cert)).unwrap();
+    let stream = net::TcpStream::connect(addr).unwrap();
+    let stream = tls::connect(&config, "localhost", stream).unwrap();
+    assert!(SoftClient::connect(stream).is_err());
+    server_thread.join().unwrap();
+}
+
+#[test]
+fn tls_invalid_files() {
+    let dir = std::env::temp_dir().join("soft_test_tls_invalid_files");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let empty = dir.join("empty.pem");
+    fs::write(&empty, "").unwrap();
+    match tls::server_config_from_files(&empty, &empty) {
+        Err(Error(ErrorKind::Tls(_), _)) => {}
+        r => panic!("expected TLS error, got {:?}", r.map(|_| ())),
+    }
+    assert!(tls::client_config_from_file(Some(dir.join("missing.pem"))).is_err());
+}
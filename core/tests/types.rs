@@ -1,6 +1,7 @@
 extern crate soft_core;
 
-use soft_core::types::Command;
+use soft_core::types::{Capability, Command, DirEntry, FileKind, Metadata, Permissions, quote_arg,
+                        split_args};
 
 #[test]
 fn command_from_str() {
@@ -23,14 +24,61 @@ fn command_from_str() {
                Command::Rmdir("path".into(), true));
     assert_eq!(Command::try_from("PRESENCE").unwrap(), Command::Presence);
     assert_eq!(Command::try_from("EXIT").unwrap(), Command::Exit);
+    assert_eq!(Command::try_from("GET /path 42").unwrap(),
+               Command::GetAt("/path".into(), 42));
+    assert_eq!(Command::try_from("PUT /path 42").unwrap(),
+               Command::PutAt("/path".into(), 42));
+    assert_eq!(Command::try_from("SIZE /path").unwrap(),
+               Command::Size("/path".into()));
+    assert_eq!(Command::try_from("LIST /path LONG").unwrap(),
+               Command::ListLong("/path".into()));
+    assert_eq!(Command::try_from("MV src dst").unwrap(),
+               Command::Mv("src".into(), "dst".into()));
+    assert_eq!(Command::try_from("CP src dst true").unwrap(),
+               Command::Cp("src".into(), "dst".into(), true));
+    assert_eq!(Command::try_from("STAT /path").unwrap(),
+               Command::Stat("/path".into()));
+    assert_eq!(Command::try_from("HELLO 1").unwrap(),
+               Command::Hello(1, Vec::new()));
+    assert_eq!(Command::try_from("HELLO 2 NEWCAP").unwrap(),
+               Command::Hello(2, vec![Capability::Unknown("NEWCAP".into())]));
+    assert_eq!(Command::try_from("AUTH user 0a1b").unwrap(),
+               Command::Auth("user".into(), "0a1b".into()));
+    assert_eq!(Command::try_from("PROOF 0a1b").unwrap(),
+               Command::Proof("0a1b".into()));
+    assert_eq!(Command::try_from("USERADD user pass").unwrap(),
+               Command::UserAdd("user".into(), "pass".into(), None));
+    assert_eq!(Command::try_from("USERADD user pass rl").unwrap(),
+               Command::UserAdd("user".into(), "pass".into(), Some(Permissions::read_only())));
+    assert_eq!(Command::try_from("USERDEL user").unwrap(), Command::UserDel("user".into()));
+    assert_eq!(Command::try_from("PASSWD pass").unwrap(), Command::Passwd(None, "pass".into()));
+    assert_eq!(Command::try_from("PASSWD user pass").unwrap(),
+               Command::Passwd(Some("user".into()), "pass".into()));
+    assert_eq!(Command::try_from("USERS").unwrap(), Command::Users);
+    assert_eq!(Command::try_from("RESUME file").unwrap(), Command::Resume("file".into()));
     assert!(Command::try_from("LOGIN BLA").is_err());
+    assert!(Command::try_from("USERADD user").is_err());
+    assert!(Command::try_from("USERADD user pass rx").is_err());
+    assert!(Command::try_from("USERDEL").is_err());
+    assert!(Command::try_from("PASSWD").is_err());
+    assert!(Command::try_from("AUTH user").is_err());
+    assert!(Command::try_from("PROOF").is_err());
     assert!(Command::try_from("GET hehe hehe").is_err());
     assert!(Command::try_from("PUT path path2").is_err());
     assert!(Command::try_from("LIST p p").is_err());
+    assert!(Command::try_from("LIST p LONG p").is_err());
     assert!(Command::try_from("CD").is_err());
     assert!(Command::try_from("MKDIR").is_err());
     assert!(Command::try_from("RM").is_err());
     assert!(Command::try_from("RMDIR").is_err());
+    assert!(Command::try_from("GET path -1").is_err());
+    assert!(Command::try_from("PUT path offset").is_err());
+    assert!(Command::try_from("SIZE").is_err());
+    assert!(Command::try_from("MV src").is_err());
+    assert!(Command::try_from("CP src dst").is_err());
+    assert!(Command::try_from("CP src dst yes").is_err());
+    assert!(Command::try_from("HELLO").is_err());
+    assert!(Command::try_from("HELLO one").is_err());
     assert!(Command::try_from("login user pass").is_err());
 }
 
@@ -49,6 +97,129 @@ fn command_to_str() {
                "RMDIR path true");
     assert_eq!(Command::Presence.to_string(), "PRESENCE");
     assert_eq!(Command::Exit.to_string(), "EXIT");
+    assert_eq!(Command::GetAt("/path".into(), 42).to_string(), "GET /path 42");
+    assert_eq!(Command::PutAt("/path".into(), 42).to_string(), "PUT /path 42");
+    assert_eq!(Command::Size("/path".into()).to_string(), "SIZE /path");
+    assert_eq!(Command::Stat("/path".into()).to_string(), "STAT /path");
+    assert_eq!(Command::ListLong("/path".into()).to_string(), "LIST /path LONG");
+    assert_eq!(Command::Mv("src".into(), "dst".into()).to_string(), "MV src dst");
+    assert_eq!(Command::Cp("src".into(), "dst".into(), false).to_string(),
+               "CP src dst false");
+    assert_eq!(Command::Auth("user".into(), "0a1b".into()).to_string(), "AUTH user 0a1b");
+    assert_eq!(Command::Proof("0a1b".into()).to_string(), "PROOF 0a1b");
+    assert_eq!(Command::UserAdd("user".into(), "pass".into(), None).to_string(),
+               "USERADD user pass");
+    assert_eq!(Command::UserAdd("user".into(), "pass".into(), Some(Permissions::all()))
+                   .to_string(),
+               "USERADD user pass rwdml");
+    assert_eq!(Command::UserDel("user".into()).to_string(), "USERDEL user");
+    assert_eq!(Command::Passwd(None, "pass".into()).to_string(), "PASSWD pass");
+    assert_eq!(Command::Passwd(Some("user".into()), "pass".into()).to_string(),
+               "PASSWD user pass");
+    assert_eq!(Command::Users.to_string(), "USERS");
+    assert_eq!(Command::Resume("file".into()).to_string(), "RESUME file");
+    assert_eq!(Command::Hello(1, Vec::new()).to_string(), "HELLO 1");
+    assert_eq!(Command::Hello(2, vec![Capability::Unknown("NEWCAP".into())]).to_string(),
+               "HELLO 2 NEWCAP");
+}
+
+#[test]
+fn command_quoted_args() {
+    assert_eq!(Command::try_from("GET \"my file.txt\"").unwrap(),
+               Command::Get("my file.txt".into()));
+    assert_eq!(Command::try_from("LOGIN \"\" \"pa ss\"").unwrap(),
+               Command::Login("".into(), "pa ss".into()));
+    assert_eq!(Command::try_from("PUT \"a\\\"b\\\\c\\nd\"").unwrap(),
+               Command::Put("a\"b\\c\nd".into()));
+    assert_eq!(Command::Get("my file.txt".into()).to_string(),
+               "GET \"my file.txt\"");
+    assert_eq!(Command::Rmdir("".into(), false).to_string(), "RMDIR \"\" false");
+    assert!(Command::try_from("").is_err());
+    assert!(Command::try_from("GET \"unterminated").is_err());
+    assert!(Command::try_from("GET \"bad\\escape\"").is_err());
+    assert!(Command::try_from("GET \"a\"b").is_err());
+    assert!(Command::try_from("GET a\"b").is_err());
+    assert!(Command::try_from("RMDIR path maybe").is_err());
+}
+
+#[test]
+fn command_round_trip() {
+    let paths = ["simple",
+                 "/with space/file.txt",
+                 "",
+                 "\"quoted\"",
+                 "back\\slash",
+                 "new\nline",
+                 "tab\tand\rreturn",
+                 "ünïcødé 文件",
+                 "  leading and trailing  "];
+    for path in paths.iter() {
+        let path = path.to_string();
+        let commands = vec![Command::Login(path.clone(), path.clone()),
+                            Command::Get(path.clone()),
+                            Command::Put(path.clone()),
+                            Command::List(path.clone()),
+                            Command::Cd(path.clone()),
+                            Command::Mkdir(path.clone()),
+                            Command::Rm(path.clone()),
+                            Command::Rmdir(path.clone(), true),
+                            Command::GetAt(path.clone(), 7),
+                            Command::PutAt(path.clone(), 7),
+                            Command::Size(path.clone()),
+                            Command::Stat(path.clone()),
+                            Command::ListLong(path.clone()),
+                            Command::Mv(path.clone(), path.clone()),
+                            Command::Cp(path.clone(), path.clone(), true)];
+        for command in commands {
+            let line = command.to_string();
+            assert!(!line.contains('\n'));
+            assert_eq!(Command::try_from(&line).unwrap(), command);
+        }
+        assert_eq!(split_args(&quote_arg(&path)).unwrap(), vec![path]);
+    }
+}
+
+#[test]
+fn metadata_round_trip() {
+    let file = Metadata {
+        kind: FileKind::File,
+        size: 1024,
+        modified: 1500000000,
+        permissions: 0o644,
+        symlink_target: None,
+    };
+    assert_eq!(file.to_string(), "file 1024 1500000000 644");
+    assert_eq!(Metadata::try_from(file.to_string()).unwrap(), file);
+    let link = Metadata {
+        kind: FileKind::Symlink,
+        size: 12,
+        modified: 0,
+        permissions: 0o777,
+        symlink_target: Some("../target file".into()),
+    };
+    assert_eq!(link.to_string(), "symlink 12 0 777 \"../target file\"");
+    assert_eq!(Metadata::try_from(link.to_string()).unwrap(), link);
+    assert!(Metadata::try_from("file 12").is_err());
+    assert!(Metadata::try_from("block 1 2 644").is_err());
+    assert!(Metadata::try_from("dir 1 2 999").is_err());
+}
+
+#[test]
+fn dir_entry_round_trip() {
+    let entry = DirEntry {
+        name: "new\nline file".into(),
+        metadata: Metadata {
+            kind: FileKind::Dir,
+            size: 4096,
+            modified: 1500000000,
+            permissions: 0o755,
+            symlink_target: None,
+        },
+    };
+    assert_eq!(entry.to_string(), "\"new\\nline file\" dir 4096 1500000000 755");
+    assert_eq!(DirEntry::try_from(entry.to_string()).unwrap(), entry);
+    assert!(DirEntry::try_from("").is_err());
+    assert!(DirEntry::try_from("name dir 1").is_err());
 }
 
 #[test]
@@ -72,6 +243,9 @@ fn command_unwrap_path() {
     assert_eq!(Command::Mkdir("path".into()).unwrap_path(), "path");
     assert_eq!(Command::Rm("path".into()).unwrap_path(), "path");
     assert_eq!(Command::Rmdir("path".into(), false).unwrap_path(), "path");
+    assert_eq!(Command::GetAt("path".into(), 1).unwrap_path(), "path");
+    assert_eq!(Command::PutAt("path".into(), 1).unwrap_path(), "path");
+    assert_eq!(Command::Size("path".into()).unwrap_path(), "path");
 }
 
 #[test]
@@ -0,0 +1,197 @@
+extern crate soft_core;
+
+use soft_core::error::*;
+use soft_core::server::auth::{Authenticator, Permissions};
+use soft_core::server::users::*;
+use std::fs;
+use std::time::Instant;
+
+#[test]
+fn password_round_trip() {
+    let password = Password::hash("secret").unwrap();
+    let record = password.to_string();
+    assert!(record.starts_with("$2$scram-sha-256$"));
+    assert!(!record.contains("secret"));
+    assert_eq!(Password::try_from(&record).unwrap(), password);
+    assert!(password.verify("secret"));
+    assert!(!password.verify("secrets"));
+    assert!(!password.is_outdated());
+    assert_ne!(Password::hash("secret").unwrap(), password);
+}
+
+#[test]
+fn password_records() {
+    assert_eq!(Password::try_from("secret").unwrap(),
+               Password::Plain("secret".to_string()));
+    assert!(Password::Plain("secret".to_string()).is_outdated());
+    assert!(Password::hash_with("secret", vec![0; 16], 1).is_outdated());
+    for record in &["$1$md5$1$00$00", "$1$pbkdf2-sha256$x$00$00", "$1$pbkdf2-sha256$1$0g$00"] {
+        match Password::try_from(record) {
+            Err(Error(ErrorKind::InvalidUserRecord, _)) |
+            Err(Error(ErrorKind::Int(_), _)) => {}
+            r => panic!("{} should be invalid, got {:?}", record, r),
+        }
+    }
+}
+
+#[test]
+fn unknown_user_timing() {
+    let users = Users::new();
+    users.add_user("alice", "secret").unwrap();
+    let start = Instant::now();
+    assert!(!users.is_valid("alice", "wrong"));
+    let known = start.elapsed();
+    let start = Instant::now();
+    assert!(!users.is_valid("bob", "wrong"));
+    let unknown = start.elapsed();
+    assert!(unknown > known / 4, "{:?} for unknown user, {:?} for known", unknown, known);
+}
+
+#[test]
+fn plaintext_migration() {
+    let dir = std::env::temp_dir().join("soft_test_plaintext_migration");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("users.db");
+    fs::write(&path, "alice secret\n").unwrap();
+    {
+        let users = Users::load_file(&path).unwrap();
+        assert!(!users.is_valid("alice", "wrong"));
+        assert_eq!(users.password("alice"), Some(Password::Plain("secret".to_string())));
+        assert!(users.is_valid("alice", "secret"));
+        assert!(!users.password("alice").unwrap().is_outdated());
+    }
+    let db = fs::read_to_string(&path).unwrap();
+    assert!(db.starts_with("alice $2$scram-sha-256$"));
+    assert!(!db.contains("secret"));
+    let users = Users::load_file(&path).unwrap();
+    assert!(users.is_valid("alice", "secret"));
+    assert!(!users.is_valid("alice", "wrong"));
+    assert!(!users.is_valid("bob", "secret"));
+}
+
+#[test]
+fn pbkdf2_migration() {
+    let v1 = "$1$pbkdf2-sha256$1$00$a332ce1b72f557ce96ae0c4fa502f385b171d729bcd3d39a51111c03b86352bb";
+    let password = Password::try_from(v1).unwrap();
+    assert_eq!(password.to_string(), v1);
+    assert!(password.verify("secret"));
+    assert!(!password.verify("wrong"));
+    assert!(password.is_outdated());
+    let scram = Password::hash_with("secret", vec![0], 1);
+    assert_eq!(Password::Scram(password.scram_keys().unwrap()), scram);
+    assert!(scram.verify("secret"));
+
+    let users = Users::new();
+    users.add_user("alice", "secret").unwrap();
+    let keys = users.scram_keys("alice").unwrap();
+    assert_eq!(users.password("alice"), Some(Password::Scram(keys.clone())));
+    assert_eq!(Password::hash_with("secret", keys.salt.clone(), keys.iterations),
+               Password::Scram(keys));
+    assert!(users.scram_keys("bob").is_none());
+}
+
+#[test]
+fn user_permissions() {
+    assert_eq!(Permissions::try_from("rl").unwrap(), Permissions::read_only());
+    assert_eq!(Permissions::try_from("-").unwrap(), Permissions::none());
+    assert_eq!(Permissions::try_from("lmdwr").unwrap(), Permissions::all());
+    assert_eq!(Permissions::all().to_string(), "rwdml");
+    assert_eq!(Permissions::read_only().to_string(), "rl");
+    assert_eq!(Permissions::none().to_string(), "-");
+    assert!(Permissions::try_from("").is_err());
+    assert!(Permissions::try_from("rx").is_err());
+
+    let dir = std::env::temp_dir().join("soft_test_user_permissions");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("users.db");
+    fs::write(&path, "alice secret rl\nbob secret\n").unwrap();
+    {
+        let users = Users::load_file(&path).unwrap();
+        assert_eq!(users.permissions("alice"), Some(Permissions::read_only()));
+        assert_eq!(users.permissions("bob"), Some(Permissions::all()));
+        assert_eq!(users.permissions("carol"), None);
+        assert_eq!(users.authenticate("alice", "secret").unwrap().permissions,
+                   Permissions::read_only());
+        assert!(users.set_permissions("bob", Permissions::none()).unwrap());
+        assert!(!users.set_permissions("carol", Permissions::none()).unwrap());
+        assert_eq!(users.identity("bob").unwrap().permissions, Permissions::none());
+    }
+    let users = Users::load_file(&path).unwrap();
+    assert_eq!(users.permissions("alice"), Some(Permissions::read_only()));
+    assert_eq!(users.permissions("bob"), Some(Permissions::none()));
+    fs::write(dir.join("invalid.db"), "alice secret rx\n").unwrap();
+    match Users::load_file(dir.join("invalid.db")) {
+        Err(Error(ErrorKind::InvalidUserDB(1), _)) => {}
+        r => panic!("expected invalid user db, got {:?}", r.map(|_| ())),
+    }
+}
+
+#[test]
+fn user_management() {
+    let dir = std::env::temp_dir().join("soft_test_user_management");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("users.db");
+    {
+        let users = Users::load_file(&path).unwrap();
+        Authenticator::add_user(&users, "alice", "secret", Permissions::read_only()).unwrap();
+        Authenticator::add_user(&users, "bob", "secret", Permissions::all()).unwrap();
+        match Authenticator::add_user(&users, "alice", "other", Permissions::all()) {
+            Err(Error(ErrorKind::UserExists(ref user), _)) if user == "alice" => {}
+            r => panic!("expected existing user, got {:?}", r),
+        }
+        assert!(users.authenticate("alice", "secret").is_some());
+        assert!(users.set_password("alice", "changed").unwrap());
+        assert!(!users.set_password("carol", "changed").unwrap());
+        assert!(users.authenticate("alice", "secret").is_none());
+        assert!(users.remove_user("bob").unwrap());
+        assert!(!users.remove_user("bob").unwrap());
+        match Authenticator::remove_user(&users, "bob") {
+            Err(Error(ErrorKind::UserUnknown(ref user), _)) if user == "bob" => {}
+            r => panic!("expected unknown user, got {:?}", r),
+        }
+    }
+    let users = Users::load_file(&path).unwrap();
+    let list = users.list();
+    assert_eq!(list.len(), 1);
+    assert_eq!(list[0].user, "alice");
+    assert_eq!(list[0].permissions, Permissions::read_only());
+    assert_eq!(users.list_users().unwrap(), list);
+    assert!(users.authenticate("alice", "changed").is_some());
+}
+
+#[test]
+fn database_persistence() {
+    let dir = std::env::temp_dir().join("soft_test_database_persistence");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("users.db");
+    let users = Users::load_file(&path).unwrap();
+    users.add_user("alice", "secret").unwrap();
+    users.add_user_with("bob", "secret", Permissions::read_only()).unwrap();
+    match users.add_user("alice", "other") {
+        Err(Error(ErrorKind::UserExists(ref user), _)) if user == "alice" => {}
+        r => panic!("expected existing user, got {:?}", r),
+    }
+    for name in &["", ".", "..", "a b", "a\tb", "a/b", "a\u{7}b"] {
+        match users.add_user(name, "secret") {
+            Err(Error(ErrorKind::InvalidUserName(ref user), _)) if user == name => {}
+            r => panic!("expected invalid user name, got {:?}", r),
+        }
+    }
+    // Changes are saved without dropping database, as if server was killed
+    std::mem::forget(users);
+    let users = Users::load_file(&path).unwrap();
+    assert!(users.authenticate("alice", "secret").is_some());
+    assert_eq!(users.permissions("bob"), Some(Permissions::read_only()));
+    assert_eq!(users.list().len(), 2);
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+    fs::write(dir.join("invalid.db"), "alice secret\nbob\n").unwrap();
+    match Users::load_file(dir.join("invalid.db")) {
+        Err(Error(ErrorKind::InvalidUserDB(2), _)) => {}
+        r => panic!("expected invalid line 2, got {:?}", r.map(|_| ())),
+    }
+}
@@ -1,21 +1,136 @@
 extern crate soft_core;
+extern crate tokio;
 
-use soft_core::server::SoftServer;
+use soft_core::server::{SoftServer, SoftServerBuilder};
+use soft_core::server::auth::{Authenticator, HtpasswdFile, Permissions, StaticUsers};
+use soft_core::server::storage::LocalStorage;
+use soft_core::server::users;
+use soft_core::tls::{self, ServerConfig};
+use std::env;
 use std::net::TcpListener;
+use std::process;
+use std::sync::Arc;
+
+const USAGE: &str = "usage: softd [--root <dir>] [--users <file>] [--home <template>] \
+                     [--htpasswd <file> | --user-list <file>] \
+                     [--tls-cert <file> --tls-key <file> | --async]";
 
 pub fn main() {
-    // TODO cli parsing and configuration
-    let mut server = SoftServer::new("soft-daemon", Some(8), true).unwrap();
-    server.get_users().add_user("soft", "soft");
+    // TODO more configuration
+    let mut builder = SoftServerBuilder::named("soft-daemon")
+        .unwrap()
+        .max_threads(8)
+        .allow_anonymous(true);
+    let mut tls_cert = None;
+    let mut tls_key = None;
+    let mut htpasswd = None;
+    let mut user_list = None;
+    let mut use_async = false;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--async" {
+            use_async = true;
+            continue;
+        }
+        let value = match args.next() {
+            Some(value) => value,
+            None => usage(),
+        };
+        builder = match arg.as_str() {
+            "--root" => builder.root(value),
+            "--users" => builder.user_db(value),
+            "--home" => builder.home(&value),
+            "--tls-cert" => {
+                tls_cert = Some(value);
+                builder
+            }
+            "--tls-key" => {
+                tls_key = Some(value);
+                builder
+            }
+            "--htpasswd" => {
+                htpasswd = Some(value);
+                builder
+            }
+            "--user-list" => {
+                user_list = Some(value);
+                builder
+            }
+            _ => usage(),
+        };
+    }
+    let tls_config = match (tls_cert, tls_key) {
+        (Some(cert), Some(key)) => Some(tls::server_config_from_files(cert, key).unwrap()),
+        (None, None) => None,
+        _ => usage(),
+    };
+    let mode = match (tls_config, use_async) {
+        (Some(config), false) => Mode::Tls(config),
+        (None, false) => Mode::Plain,
+        (None, true) => Mode::Async,
+        _ => usage(),
+    };
+    match (htpasswd, user_list) {
+        (Some(path), None) => {
+            serve(builder.build_with_authenticator(HtpasswdFile::open(path).unwrap()).unwrap(),
+                  mode)
+        }
+        (None, Some(path)) => {
+            serve(builder.build_with_authenticator(StaticUsers::load(path).unwrap()).unwrap(),
+                  mode)
+        }
+        (None, None) => {
+            let server = builder.build().unwrap();
+            let users = server.get_users();
+            if users.list().is_empty() {
+                let admin = Permissions { admin: true, ..Permissions::all() };
+                let password = users::random_password().unwrap();
+                users.add_user_with("soft", &password, admin).unwrap();
+                println!("Created administrator soft with password {}", password);
+            }
+            serve(server, mode)
+        }
+        _ => usage(),
+    }
+}
+
+/// Way to handle clients
+enum Mode {
+    /// Plain connections on a pool of threads
+    Plain,
+    /// TLS connections on a pool of threads
+    Tls(Arc<ServerConfig>),
+    /// Plain connections as tokio tasks
+    ///
+    /// Idle clients hold no thread, but each running command, transfers included, holds one
+    /// of the blocking pool of tokio. There is no graceful shutdown in this mode.
+    Async,
+}
+
+/// Accept clients forever
+fn serve<A: Authenticator>(mut server: SoftServer<LocalStorage, A>, mode: Mode) {
     let listener = TcpListener::bind(("127.0.0.1", soft_core::DEFAULT_PORT)).unwrap();
     println!("Listening for client...");
-    for stream in listener.incoming() {
-        println!("New client connected");
-        match stream {
-            Ok(stream) => {
-                server.new_connection(stream);
-            }
-            Err(e) => panic!("{}", e),
+    match mode {
+        Mode::Plain => server.serve(listener).unwrap(),
+        Mode::Tls(config) => {
+            server.serve_with(listener, |stream| tls::accept(&config, stream)).unwrap()
         }
+        Mode::Async => serve_async(server, listener),
     }
 }
+
+/// Accept clients forever on a tokio runtime
+fn serve_async<A: Authenticator>(server: SoftServer<LocalStorage, A>, listener: TcpListener) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let _guard = runtime.enter();
+    listener.set_nonblocking(true).unwrap();
+    let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+    runtime.block_on(server.into_async().serve(listener)).unwrap();
+}
+
+/// Print usage and exit
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(1)
+}